@@ -1,12 +1,21 @@
+use std::pin::Pin;
+
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures::Stream;
+use tokio::sync::mpsc;
 use uuid::Uuid;
 
 use crate::auth::User;
 use crate::entities::{
-    Coordinates, Driver, Location, LocationSource, Passenger, Quote, Route, Trip,
+    Coordinates, Driver, DriverStats, Location, LocationSample, LocationSource, OfferUpdate,
+    Passenger, Quote, Route, SafetyAlert, ScheduledTripListing, SurgeCell, Trip, TripEvent,
+    TripTrace, TripUpdate, Vehicle, VehicleClass,
 };
 use crate::error::Error;
 
+pub type Subscription<T> = Pin<Box<dyn Stream<Item = T> + Send>>;
+
 #[async_trait]
 pub trait LocationAPI {
     async fn create_location(&self, user: User, source: LocationSource) -> Result<Location, Error>;
@@ -19,6 +28,7 @@ pub trait RouteAPI {
         &self,
         user: User,
         origin_token: Uuid,
+        stop_tokens: Vec<Uuid>,
         destination_token: Uuid,
     ) -> Result<Route, Error>;
     async fn find_route(&self, user: User, token: Uuid) -> Result<Route, Error>;
@@ -26,21 +36,56 @@ pub trait RouteAPI {
 
 #[async_trait]
 pub trait QuoteAPI {
-    async fn create_quote(&self, user: User, route_token: Uuid) -> Result<Option<Quote>, Error>;
+    async fn create_quote(
+        &self,
+        user: User,
+        route_token: Uuid,
+        pickup_at: Option<DateTime<Utc>>,
+        is_pooled: bool,
+        vehicle_class: VehicleClass,
+    ) -> Result<Option<Quote>, Error>;
     async fn find_quote(&self, user: User, token: Uuid) -> Result<Quote, Error>;
 }
 
 #[async_trait]
 pub trait TripAPI {
-    async fn create_trip(&self, user: User, quote_token: Uuid) -> Result<Trip, Error>;
+    async fn create_trip(
+        &self,
+        user: User,
+        quote_token: Uuid,
+        wait_for_driver: bool,
+    ) -> Result<Trip, Error>;
     async fn find_trip(&self, user: User, id: Uuid) -> Result<Trip, Error>;
+    async fn find_trip_history(&self, user: User, id: Uuid) -> Result<Vec<TripEvent>, Error>;
+    async fn find_trip_trace(&self, user: User, id: Uuid) -> Result<TripTrace, Error>;
+    async fn subscribe_trip(&self, user: User, id: Uuid)
+        -> Result<Subscription<TripUpdate>, Error>;
     async fn request_driver(&self, user: User, id: Uuid) -> Result<Option<Trip>, Error>;
     async fn release_driver(&self, user: User, id: Uuid, driver_id: Uuid) -> Result<Trip, Error>;
     async fn accept_trip(&self, user: User, id: Uuid) -> Result<Trip, Error>;
     async fn reject_trip(&self, user: User, id: Uuid) -> Result<Trip, Error>;
     async fn cancel_trip(&self, user: User, id: Uuid) -> Result<Trip, Error>;
     async fn report_origin_arrival(&self, user: User, id: Uuid) -> Result<Trip, Error>;
+    async fn report_stop_arrival(&self, user: User, id: Uuid) -> Result<Trip, Error>;
     async fn report_destination_arrival(&self, user: User, id: Uuid) -> Result<Trip, Error>;
+    async fn change_destination(
+        &self,
+        user: User,
+        id: Uuid,
+        stop_tokens: Option<Vec<Uuid>>,
+        destination_token: Uuid,
+    ) -> Result<Trip, Error>;
+    async fn acknowledge_route_change(&self, user: User, id: Uuid) -> Result<Trip, Error>;
+    async fn rate_trip(&self, user: User, id: Uuid, rating: i32) -> Result<Trip, Error>;
+}
+
+#[async_trait]
+pub trait SchedulingAPI {
+    async fn find_scheduled_trips(&self, user: User) -> Result<Vec<Trip>, Error>;
+    async fn find_scheduled_listings(&self, user: User)
+        -> Result<Vec<ScheduledTripListing>, Error>;
+    async fn commit_to_trip(&self, user: User, id: Uuid) -> Result<Trip, Error>;
+    async fn withdraw_from_trip(&self, user: User, id: Uuid) -> Result<Trip, Error>;
 }
 
 #[async_trait]
@@ -49,6 +94,11 @@ pub trait DriverAPI {
     async fn find_driver(&self, user: User, id: Uuid) -> Result<Driver, Error>;
     async fn start_driver(&self, user: User, id: Uuid) -> Result<Driver, Error>;
     async fn stop_driver(&self, user: User, id: Uuid) -> Result<Driver, Error>;
+    async fn subscribe_offers(
+        &self,
+        user: User,
+        id: Uuid,
+    ) -> Result<Subscription<OfferUpdate>, Error>;
     async fn update_driver_rate(
         &self,
         user: User,
@@ -56,6 +106,9 @@ pub trait DriverAPI {
         min_fare: f64,
         rate: f64,
     ) -> Result<(), Error>;
+    async fn find_driver_stats(&self, user: User, id: Uuid) -> Result<DriverStats, Error>;
+    async fn register_vehicle(&self, user: User, vehicle: Vehicle) -> Result<Vehicle, Error>;
+    async fn find_vehicle(&self, user: User, id: Uuid) -> Result<Vehicle, Error>;
 }
 
 #[async_trait]
@@ -65,7 +118,13 @@ pub trait DriverLocationAPI {
         user: User,
         id: Uuid,
         coordinates: Coordinates,
+        is_mock: bool,
     ) -> Result<(), Error>;
+    async fn ingest_driver_locations(
+        &self,
+        user: User,
+        id: Uuid,
+    ) -> Result<mpsc::Sender<Vec<LocationSample>>, Error>;
 }
 
 #[async_trait]
@@ -79,17 +138,37 @@ pub trait PassengerAPI {
     async fn create_passenger(&self, user: User) -> Result<Passenger, Error>;
 }
 
+#[async_trait]
+pub trait SurgeAPI {
+    async fn find_surge(&self, user: User) -> Result<Vec<SurgeCell>, Error>;
+}
+
+#[async_trait]
+pub trait SafetyAPI {
+    async fn find_safety_alerts(&self, user: User) -> Result<Vec<SafetyAlert>, Error>;
+    async fn find_trip_alerts(&self, user: User, id: Uuid) -> Result<Vec<SafetyAlert>, Error>;
+}
+
 // service boundaries
 pub trait LocationService: LocationAPI {}
 
 pub trait RouteService: RouteAPI {}
 
-pub trait BookingService: TripAPI + DriverAPI + PassengerAPI {}
+pub trait BookingService: TripAPI + SchedulingAPI + DriverAPI + PassengerAPI + SafetyAPI {}
 
-pub trait DriverSearchService: DriverSearchAPI + DriverLocationAPI + QuoteAPI {}
+pub trait DriverSearchService: DriverSearchAPI + DriverLocationAPI + QuoteAPI + SurgeAPI {}
 
 // complete api
 pub trait API:
-    LocationAPI + RouteAPI + QuoteAPI + TripAPI + DriverAPI + DriverLocationAPI + PassengerAPI
+    LocationAPI
+    + RouteAPI
+    + QuoteAPI
+    + TripAPI
+    + SchedulingAPI
+    + DriverAPI
+    + DriverLocationAPI
+    + PassengerAPI
+    + SurgeAPI
+    + SafetyAPI
 {
 }
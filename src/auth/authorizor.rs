@@ -1,7 +1,7 @@
 use oso::{Oso, PolarClass};
 
 use crate::auth::{Platform, User};
-use crate::entities::{Driver, Trip};
+use crate::entities::{Driver, Quote, Trip};
 
 pub fn new() -> Oso {
     let mut o = Oso::new();
@@ -9,6 +9,7 @@ pub fn new() -> Oso {
     o.register_class(Platform::get_polar_class()).unwrap();
     o.register_class(User::get_polar_class()).unwrap();
     o.register_class(Driver::get_polar_class()).unwrap();
+    o.register_class(Quote::get_polar_class()).unwrap();
     o.register_class(Trip::get_polar_class()).unwrap();
 
     o.load_str(include_str!("rules.polar")).unwrap();
@@ -20,14 +21,18 @@ pub fn new() -> Oso {
 mod tests {
     use super::*;
 
-    use crate::entities::{Coordinates, Location, Route, Trip};
+    use chrono::Duration;
+
+    use crate::entities::{
+        BroadcastOffer, Coordinates, Driver, FareBreakdown, Location, Quote, Route, Trip,
+    };
     use uuid::Uuid;
 
     fn new_trip(passenger_id: Uuid) -> Trip {
         let origin = Location::new(Coordinates { lat: 0.0, lng: 0.0 }, "".into());
         let destination = origin.clone();
         let route = Route::new(origin, destination, serde_json::json!({}), 100.0);
-        Trip::new(passenger_id, route, 100.0)
+        Trip::new(passenger_id, route, 100.0, 1.0)
     }
 
     #[test]
@@ -59,6 +64,41 @@ mod tests {
         assert!(result.unwrap().next().unwrap().is_ok());
     }
 
+    #[test]
+    fn quote_passenger_role_test() {
+        let authorizor = new();
+
+        let passenger = User {
+            id: Uuid::new_v4(),
+            roles: vec![],
+        };
+
+        let other = User {
+            id: Uuid::new_v4(),
+            roles: vec![],
+        };
+
+        let trip = new_trip(passenger.id);
+        let quote = Quote::new(
+            passenger.id,
+            trip.route,
+            FareBreakdown::fixed(100.0),
+            Duration::minutes(5),
+        );
+
+        let result = authorizor.is_allowed(passenger.clone(), "read", quote.clone());
+        assert!(result.unwrap());
+
+        let result = authorizor.is_allowed(passenger.clone(), "book", quote.clone());
+        assert!(result.unwrap());
+
+        let result = authorizor.is_allowed(other.clone(), "read", quote.clone());
+        assert!(!result.unwrap());
+
+        let result = authorizor.is_allowed(other.clone(), "book", quote.clone());
+        assert!(!result.unwrap());
+    }
+
     #[test]
     fn trip_passenger_role_test() {
         let authorizor = new();
@@ -79,6 +119,16 @@ mod tests {
 
         let result = authorizor.is_allowed(passenger.clone(), "cancel", trip.clone());
         assert_eq!(result.unwrap(), true);
+
+        let result = authorizor.is_allowed(passenger.clone(), "change_destination", trip.clone());
+        assert!(result.unwrap());
+
+        let result =
+            authorizor.is_allowed(passenger.clone(), "acknowledge_route_change", trip.clone());
+        assert!(!result.unwrap());
+
+        let result = authorizor.is_allowed(passenger.clone(), "rate", trip.clone());
+        assert!(result.unwrap());
     }
 
     #[test]
@@ -115,7 +165,8 @@ mod tests {
         let result = authorizor.is_allowed(driver.clone(), "cancel", trip.clone());
         assert_eq!(result.unwrap(), false);
 
-        trip.request_driver(driver.id.clone(), 100.0).unwrap();
+        trip.request_driver(driver.id.clone(), 0.0, FareBreakdown::fixed(100.0))
+            .unwrap();
 
         // after driver is requested and before driver is assigned
 
@@ -164,6 +215,118 @@ mod tests {
 
         let result = authorizor.is_allowed(driver.clone(), "cancel", trip.clone());
         assert_eq!(result.unwrap(), true);
+
+        let result =
+            authorizor.is_allowed(driver.clone(), "acknowledge_route_change", trip.clone());
+        assert!(result.unwrap());
+
+        let result = authorizor.is_allowed(driver.clone(), "change_destination", trip.clone());
+        assert!(!result.unwrap());
+
+        let result = authorizor.is_allowed(driver.clone(), "rate", trip.clone());
+        assert!(!result.unwrap());
+    }
+
+    #[test]
+    fn trip_scheduled_roles_test() {
+        let authorizor = new();
+
+        let driver = User {
+            id: Uuid::new_v4(),
+            roles: vec!["driver".into()],
+        };
+
+        let member = User {
+            id: Uuid::new_v4(),
+            roles: vec!["member".into()],
+        };
+
+        let trip = new_trip(Uuid::new_v4());
+        let mut trip = Trip::new_scheduled(
+            trip.passenger_id,
+            trip.route,
+            100.0,
+            1.0,
+            chrono::Utc::now() + chrono::Duration::hours(2),
+        );
+
+        let result = authorizor.is_allowed(driver.clone(), "commit", trip.clone());
+        assert!(result.unwrap());
+
+        // candidates only see the listing of the trip until they commit
+        let result = authorizor.is_allowed(driver.clone(), "list", trip.clone());
+        assert!(result.unwrap());
+
+        let result = authorizor.is_allowed(driver.clone(), "read", trip.clone());
+        assert!(!result.unwrap());
+
+        let result = authorizor.is_allowed(member.clone(), "commit", trip.clone());
+        assert!(!result.unwrap());
+
+        let result = authorizor.is_allowed(driver.clone(), "withdraw", trip.clone());
+        assert!(!result.unwrap());
+
+        trip.commit_driver(driver.id, FareBreakdown::fixed(50.0))
+            .unwrap();
+
+        let result = authorizor.is_allowed(driver.clone(), "withdraw", trip.clone());
+        assert!(result.unwrap());
+
+        let result = authorizor.is_allowed(driver.clone(), "read", trip.clone());
+        assert!(result.unwrap());
+
+        trip.start_search().unwrap();
+
+        let result = authorizor.is_allowed(driver.clone(), "commit", trip.clone());
+        assert!(!result.unwrap());
+
+        let result = authorizor.is_allowed(driver.clone(), "withdraw", trip.clone());
+        assert!(!result.unwrap());
+    }
+
+    #[test]
+    fn trip_broadcast_candidate_role_test() {
+        let authorizor = new();
+
+        let first = User {
+            id: Uuid::new_v4(),
+            roles: vec![],
+        };
+
+        let second = User {
+            id: Uuid::new_v4(),
+            roles: vec![],
+        };
+
+        let mut trip = new_trip(Uuid::new_v4());
+
+        let offer = |driver_id| BroadcastOffer {
+            driver_id,
+            pickup_distance: 0.0,
+            fare: 50.0,
+            breakdown: FareBreakdown::fixed(50.0),
+        };
+
+        trip.broadcast(vec![offer(first.id), offer(second.id)])
+            .unwrap();
+
+        let result = authorizor.is_allowed(first.clone(), "accept", trip.clone());
+        assert!(result.unwrap());
+
+        let result = authorizor.is_allowed(second.clone(), "accept", trip.clone());
+        assert!(result.unwrap());
+
+        let withdrawn = trip.accept_offer(second.id).unwrap();
+        assert_eq!(withdrawn, vec![first.id]);
+
+        let result = authorizor.is_allowed(first.clone(), "accept", trip.clone());
+        assert!(!result.unwrap());
+
+        let result = authorizor.is_allowed(first.clone(), "read", trip.clone());
+        assert!(!result.unwrap());
+
+        let result = authorizor.is_allowed(second.clone(), "report_origin_arrival", trip.clone());
+        assert!(result.unwrap());
     }
 
     #[test]
@@ -209,7 +372,8 @@ mod tests {
         let result = authorizor.is_allowed(system.clone(), "release_driver", trip.clone());
         assert_eq!(result.unwrap(), true);
 
-        trip.request_driver(Uuid::new_v4(), 100.0).unwrap();
+        trip.request_driver(Uuid::new_v4(), 0.0, FareBreakdown::fixed(100.0))
+            .unwrap();
 
         // after request driver
 
@@ -238,4 +402,55 @@ mod tests {
         let result = authorizor.is_allowed(system.clone(), "release_driver", trip.clone());
         assert_eq!(result.unwrap(), true);
     }
+
+    #[test]
+    fn driver_stats_test() {
+        let authorizor = new();
+
+        let owner = User {
+            id: Uuid::new_v4(),
+            roles: vec![],
+        };
+        let other = User {
+            id: Uuid::new_v4(),
+            roles: vec![],
+        };
+        let system = User {
+            id: Uuid::new_v4(),
+            roles: vec!["system".into()],
+        };
+
+        let driver = Driver::new(owner.id);
+
+        let result = authorizor.is_allowed(owner, "read_stats", driver.clone());
+        assert!(result.unwrap());
+
+        let result = authorizor.is_allowed(other, "read_stats", driver.clone());
+        assert!(!result.unwrap());
+
+        let result = authorizor.is_allowed(system, "read_stats", driver);
+        assert!(result.unwrap());
+    }
+
+    #[test]
+    fn driver_vehicle_test() {
+        let authorizor = new();
+
+        let owner = User {
+            id: Uuid::new_v4(),
+            roles: vec![],
+        };
+        let other = User {
+            id: Uuid::new_v4(),
+            roles: vec![],
+        };
+
+        let driver = Driver::new(owner.id);
+
+        let result = authorizor.is_allowed(owner, "update_vehicle", driver.clone());
+        assert!(result.unwrap());
+
+        let result = authorizor.is_allowed(other, "update_vehicle", driver);
+        assert!(!result.unwrap());
+    }
 }
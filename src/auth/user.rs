@@ -26,6 +26,10 @@ impl User {
         false
     }
 
+    fn id_in(&self, ids: Vec<Uuid>) -> bool {
+        ids.contains(&self.id)
+    }
+
     fn has_role(&self, role: String) -> bool {
         self.roles.iter().find(|&x| x == &role).is_some()
     }
@@ -38,6 +42,7 @@ impl PolarClass for User {
             .add_attribute_getter("id", |recv: &User| recv.id.clone())
             .add_attribute_getter("roles", |recv: &User| recv.roles.clone())
             .add_method("id_equals_nullable_id", User::id_equals_nullable_id)
+            .add_method("id_in", User::id_in)
             .add_method("has_role", User::has_role)
     }
 
@@ -0,0 +1,18 @@
+use serde::{Deserialize, Serialize};
+
+use super::matching::BatchConfig;
+
+/// How trips are offered to drivers.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum DispatchConfig {
+    // one driver at a time, waiting for each to accept or reject
+    #[default]
+    Sequential,
+    // up to `size` drivers at once, the first to accept is assigned
+    Broadcast {
+        size: usize,
+    },
+    // periodically, matching all searching trips with available drivers at once
+    Batch(BatchConfig),
+}
@@ -1,14 +1,21 @@
-use super::helpers::{fetch_driver_for_update, update_driver};
+use super::helpers::{
+    fetch_driver_for_update, fetch_driver_stats, insert_event, subscription, update_driver,
+};
+use super::surge::geohash;
 use super::Engine;
 
 use async_trait::async_trait;
 use sqlx::{types::Json, Acquire, Executor, Row};
+use tokio::sync::{broadcast::error::RecvError, mpsc};
 use uuid::Uuid;
 
 use crate::{
-    api::DriverAPI,
+    api::{DriverAPI, Subscription, TripAPI},
     auth::User,
-    entities::Driver,
+    entities::{
+        DomainEvent, Driver, DriverOffer, DriverStats, DriverStatus, OfferUpdate, Trip, TripStatus,
+        Vehicle,
+    },
     error::{invalid_input_error, Error},
 };
 
@@ -42,12 +49,6 @@ impl DriverAPI for Engine {
         ).bind(&driver.id))
         .await?;
 
-        tx.execute(
-            sqlx::query("INSERT INTO driver_priorities (driver_id, priority) VALUES ($1, 0)")
-                .bind(&driver.id),
-        )
-        .await?;
-
         tx.commit().await?;
 
         Ok(driver)
@@ -77,6 +78,17 @@ impl DriverAPI for Engine {
 
         update_driver(&mut tx, &driver).await?;
 
+        // drivers who never report a location are considered stale from the time they started
+        tx.execute(sqlx::query("UPDATE drivers SET started_at = now() WHERE id = $1").bind(id))
+            .await?;
+        insert_event(
+            &mut tx,
+            &DomainEvent::DriverStarted {
+                driver_id: driver.id,
+            },
+        )
+        .await?;
+
         tx.commit().await?;
 
         Ok(driver)
@@ -92,12 +104,93 @@ impl DriverAPI for Engine {
         driver.stop()?;
 
         update_driver(&mut tx, &driver).await?;
+        insert_event(
+            &mut tx,
+            &DomainEvent::DriverStopped {
+                driver_id: driver.id,
+            },
+        )
+        .await?;
 
         tx.commit().await?;
 
         Ok(driver)
     }
 
+    #[tracing::instrument(skip(self))]
+    async fn subscribe_offers(
+        &self,
+        user: User,
+        id: Uuid,
+    ) -> Result<Subscription<OfferUpdate>, Error> {
+        // subscribe before fetching the driver so that no offers are missed in between
+        let mut events = self.subscribe();
+
+        let driver = self.find_driver(user.clone(), id).await?;
+
+        self.authorize(user.clone(), "receive_offers", driver.clone())?;
+
+        let (sender, receiver) = mpsc::channel(16);
+        let engine = self.shared();
+
+        tokio::spawn(async move {
+            let mut offered_trip_id = None;
+
+            // an offer may already be pending when the driver subscribes
+            if let DriverStatus::Requested { trip_id } = driver.status {
+                if let Some(offer) = engine.find_offer(user.clone(), trip_id, id).await {
+                    offered_trip_id = Some(trip_id);
+
+                    if sender.send(OfferUpdate::Offer { offer }).await.is_err() {
+                        return;
+                    }
+                }
+            }
+
+            loop {
+                let update = tokio::select! {
+                    event = events.recv() => match event.map(|event| event.event) {
+                        Ok(DomainEvent::DriverRequested { trip_id, driver_id, .. }) if driver_id == id => {
+                            match engine.find_offer(user.clone(), trip_id, id).await {
+                                Some(offer) => {
+                                    offered_trip_id = Some(trip_id);
+                                    OfferUpdate::Offer { offer }
+                                }
+                                None => continue,
+                            }
+                        }
+                        Ok(DomainEvent::TripPooled { trip_id, driver_id, stops, .. }) if driver_id == id => {
+                            OfferUpdate::Pooled { trip_id, stops }
+                        }
+                        Ok(
+                            DomainEvent::DriverReleased { trip_id, driver_id }
+                            | DomainEvent::OfferWithdrawn { trip_id, driver_id },
+                        ) if driver_id == id => {
+                            offered_trip_id = None;
+                            OfferUpdate::Withdrawn { trip_id }
+                        }
+                        Ok(DomainEvent::TripCancelled { trip_id, .. }) if offered_trip_id == Some(trip_id) => {
+                            offered_trip_id = None;
+                            OfferUpdate::Withdrawn { trip_id }
+                        }
+                        Ok(DomainEvent::DriverDeactivated { driver_id }) if driver_id == id => {
+                            OfferUpdate::Deactivated
+                        }
+                        Ok(_) | Err(RecvError::Lagged(_)) => continue,
+                        Err(RecvError::Closed) => break,
+                    },
+                    _ = sender.closed() => break,
+                };
+
+                if sender.send(update).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(subscription(receiver))
+    }
+
     #[tracing::instrument(skip(self))]
     async fn update_driver_rate(
         &self,
@@ -118,4 +211,103 @@ impl DriverAPI for Engine {
 
         Ok(())
     }
+
+    #[tracing::instrument(skip(self))]
+    async fn find_driver_stats(&self, user: User, id: Uuid) -> Result<DriverStats, Error> {
+        let driver = self.find_driver(user.clone(), id).await?;
+
+        self.authorize(user, "read_stats", driver)?;
+
+        let mut stats = fetch_driver_stats(&self.pool, &[id], self.stats_window).await?;
+
+        stats.remove(&id).ok_or_else(invalid_input_error)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn register_vehicle(&self, user: User, vehicle: Vehicle) -> Result<Vehicle, Error> {
+        let driver = self.find_driver(user.clone(), vehicle.driver_id).await?;
+
+        self.authorize(user, "update_vehicle", driver)?;
+
+        if !vehicle.is_valid() {
+            return Err(invalid_input_error());
+        }
+
+        let mut conn = self.pool.acquire().await?;
+
+        // drivers have a single vehicle, registering another one replaces it
+        conn.execute(
+            sqlx::query(
+                "
+                INSERT INTO vehicles (driver_id, class, data) VALUES ($1, $2, $3)
+                ON CONFLICT (driver_id) DO UPDATE SET class = $2, data = $3
+                ",
+            )
+            .bind(vehicle.driver_id)
+            .bind(vehicle.class.name())
+            .bind(Json(&vehicle)),
+        )
+        .await?;
+
+        Ok(vehicle)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn find_vehicle(&self, user: User, id: Uuid) -> Result<Vehicle, Error> {
+        let driver = self.find_driver(user.clone(), id).await?;
+
+        self.authorize(user, "read", driver)?;
+
+        let mut conn = self.pool.acquire().await?;
+
+        let maybe_result = conn
+            .fetch_optional(sqlx::query("SELECT data FROM vehicles WHERE driver_id = $1").bind(id))
+            .await?;
+
+        let result = maybe_result.ok_or_else(invalid_input_error)?;
+        let Json(vehicle): Json<Vehicle> = result.try_get("data")?;
+
+        Ok(vehicle)
+    }
+}
+
+impl Engine {
+    /// Returns the offer of a trip to a driver, if the trip is still pending assignment to them.
+    async fn find_offer(&self, user: User, trip_id: Uuid, driver_id: Uuid) -> Option<DriverOffer> {
+        let trip = self.find_trip(user, trip_id).await.ok()?;
+
+        offer(&trip, &driver_id)
+    }
+}
+
+fn offer(trip: &Trip, driver_id: &Uuid) -> Option<DriverOffer> {
+    let (deadline, pickup_distance, fare, breakdown) = match &trip.status {
+        TripStatus::PendingAssignment {
+            deadline,
+            driver_id: requested_driver_id,
+            pickup_distance,
+            fare,
+            breakdown,
+        } if requested_driver_id == driver_id => (deadline, pickup_distance, fare, breakdown),
+        TripStatus::Broadcast { deadline, offers } => {
+            let offer = offers.iter().find(|offer| offer.driver_id == *driver_id)?;
+            (
+                deadline,
+                &offer.pickup_distance,
+                &offer.fare,
+                &offer.breakdown,
+            )
+        }
+        _ => return None,
+    };
+
+    Some(DriverOffer {
+        trip_id: trip.id,
+        pickup: trip.route.origin.clone(),
+        pickup_distance: *pickup_distance,
+        fare: *fare,
+        breakdown: breakdown.clone(),
+        destination_area: geohash(&trip.route.destination.coordinates, 5),
+        deadline: *deadline,
+    })
 }
@@ -1,13 +1,32 @@
-use super::Engine;
+use super::helpers::{fetch_driver_for_update, insert_event, update_driver};
+use super::spoofing::{SpoofingConfig, Track};
+use super::{Database, Engine};
+
+use std::collections::HashMap;
 
 use async_trait::async_trait;
-use chrono::{Duration, Utc};
+use chrono::{DateTime, Duration, Utc};
 use geo_types::Geometry;
 use geozero::wkb;
-use sqlx::Executor;
+use sqlx::{pool::PoolConnection, types::Json, Acquire, Executor, QueryBuilder};
+use tokio::sync::mpsc;
 use uuid::Uuid;
 
-use crate::{api::DriverLocationAPI, auth::User, entities::Coordinates, error::Error};
+use crate::{
+    api::{DriverAPI, DriverLocationAPI},
+    auth::User,
+    entities::{Coordinates, DomainEvent, DriverLocation, LocationFlag, LocationSample},
+    error::{invalid_input_error, Error},
+};
+
+// how long a reported location is considered current
+const LOCATION_TTL_SECONDS: i64 = 60;
+
+// how far ahead of the server clock a sample may be timestamped
+const MAX_CLOCK_SKEW_SECONDS: i64 = 10;
+
+// rows per upsert statement, well below the bind parameter limit of postgres
+const FLUSH_CHUNK_SIZE: usize = 1000;
 
 #[async_trait]
 impl DriverLocationAPI for Engine {
@@ -17,9 +36,35 @@ impl DriverLocationAPI for Engine {
         user: User,
         id: Uuid,
         coordinates: Coordinates,
+        is_mock: bool,
     ) -> Result<(), Error> {
+        let driver = self.find_driver(user.clone(), id).await?;
+
+        self.authorize(user, "update_location", driver)?;
+
+        if !coordinates.is_valid() {
+            return Err(invalid_input_error());
+        }
+
         let mut conn = self.pool.acquire().await?;
 
+        let location = DriverLocation {
+            driver_id: id,
+            coordinates: coordinates.clone(),
+            timestamp: Utc::now(),
+        };
+
+        let flags = self.check_samples(
+            id,
+            &[LocationSample {
+                coordinates: coordinates.clone(),
+                timestamp: location.timestamp,
+                is_mock,
+            }],
+        );
+
+        insert_location_flags(&mut conn, &flags).await?;
+
         let coordinates: Geometry<f64> = coordinates.into();
 
         conn.execute(
@@ -27,11 +72,374 @@ impl DriverLocationAPI for Engine {
                 "UPDATE driver_locations SET location = ST_SetSRID($2, 4326), expiry = $3 WHERE driver_id = $1",
             )
             .bind(&id)
+            .bind(wkb::Encode(coordinates.clone()))
+            .bind(Utc::now() + Duration::seconds(LOCATION_TTL_SECONDS)),
+        )
+        .await?;
+
+        conn.execute(
+            sqlx::query(
+                r#"
+                INSERT INTO trip_traces (trip_id, driver_id, location, timestamp)
+                SELECT t.trip_id::UUID, d.id, ST_SetSRID($2, 4326), $3
+                FROM drivers d, jsonb_array_elements_text(COALESCE(d.data->'status'->'trip_ids', jsonb_build_array(d.data->'status'->'trip_id'))) AS t (trip_id)
+                WHERE d.id = $1 AND d.status IN ('assigned', 'pooled')
+                "#,
+            )
+            .bind(id)
             .bind(wkb::Encode(coordinates))
-            .bind(Utc::now() + Duration::seconds(60)),
+            .bind(location.timestamp),
+        )
+        .await?;
+
+        // sending only fails when there are no subscribers
+        let _ = self.locations.send(location);
+
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn ingest_driver_locations(
+        &self,
+        user: User,
+        id: Uuid,
+    ) -> Result<mpsc::Sender<Vec<LocationSample>>, Error> {
+        let driver = self.find_driver(user.clone(), id).await?;
+
+        self.authorize(user, "update_location", driver)?;
+
+        let (sender, mut receiver) = mpsc::channel::<Vec<LocationSample>>(16);
+        let engine = self.shared();
+
+        tokio::spawn(async move {
+            while let Some(samples) = receiver.recv().await {
+                let flags = engine.check_samples(id, &samples);
+
+                if !flags.is_empty() {
+                    let result = match engine.pool.acquire().await {
+                        Ok(mut conn) => insert_location_flags(&mut conn, &flags).await,
+                        Err(err) => Err(err.into()),
+                    };
+
+                    if let Err(err) = result {
+                        tracing::warn!("failed to record location flags of {}: {:?}", id, err);
+                    }
+                }
+
+                let locations = valid_samples(id, samples);
+
+                let location = match locations.iter().max_by_key(|location| location.timestamp) {
+                    Some(location) => location.clone(),
+                    None => continue,
+                };
+
+                // every sample is kept for the trace of an active trip
+                engine.pending_trace.lock().unwrap().extend(locations);
+
+                // only the latest location of each driver is kept until the next flush
+                let mut pending = engine.pending_locations.lock().unwrap();
+                if coalesce(&mut pending, location.clone()) {
+                    let _ = engine.locations.send(location);
+                }
+            }
+        });
+
+        Ok(sender)
+    }
+}
+
+impl Engine {
+    /// Checks the samples reported by a driver for signs of spoofing against the track of the
+    /// driver, returning the flags raised.
+    fn check_samples(&self, driver_id: Uuid, samples: &[LocationSample]) -> Vec<LocationFlag> {
+        let mut tracks = self.location_tracks.lock().unwrap();
+        let track = tracks.entry(driver_id).or_default();
+
+        flag_samples(&self.spoofing, track, driver_id, samples, Utc::now())
+    }
+
+    /// Writes the locations ingested since the last flush to `driver_locations` using multi-row
+    /// upserts, keeping any stored location that is more recent, and appends them to the trace
+    /// of the trips each driver is assigned to.
+    #[tracing::instrument(skip(self))]
+    pub async fn flush_driver_locations(&self) -> Result<(), Error> {
+        let pending = std::mem::take(&mut *self.pending_locations.lock().unwrap());
+        let trace = std::mem::take(&mut *self.pending_trace.lock().unwrap());
+
+        if pending.is_empty() && trace.is_empty() {
+            return Ok(());
+        }
+
+        let locations: Vec<DriverLocation> = pending.into_values().collect();
+        let mut conn = self.pool.acquire().await?;
+
+        for chunk in locations.chunks(FLUSH_CHUNK_SIZE) {
+            let mut query =
+                QueryBuilder::new("INSERT INTO driver_locations (driver_id, location, expiry) ");
+
+            query.push_values(chunk, |mut row, location| {
+                let coordinates: Geometry<f64> = location.coordinates.clone().into();
+
+                row.push_bind(location.driver_id)
+                    .push("ST_SetSRID(")
+                    .push_bind_unseparated(wkb::Encode(coordinates))
+                    .push_unseparated(", 4326)")
+                    .push_bind(location.timestamp + Duration::seconds(LOCATION_TTL_SECONDS));
+            });
+
+            query.push(
+                " ON CONFLICT (driver_id) DO UPDATE SET location = EXCLUDED.location, expiry = EXCLUDED.expiry \
+                WHERE driver_locations.expiry IS NULL OR driver_locations.expiry < EXCLUDED.expiry",
+            );
+
+            conn.execute(query.build()).await?;
+        }
+
+        // the driver status is checked at flush time, so points reported just before an
+        // assignment ends may still be attributed to the trip
+        for chunk in trace.chunks(FLUSH_CHUNK_SIZE) {
+            let mut query = QueryBuilder::new(
+                "INSERT INTO trip_traces (trip_id, driver_id, location, timestamp) \
+                SELECT t.trip_id::UUID, d.id, v.location, v.timestamp FROM (",
+            );
+
+            query.push_values(chunk, |mut row, location| {
+                let coordinates: Geometry<f64> = location.coordinates.clone().into();
+
+                row.push_bind(location.driver_id)
+                    .push("ST_SetSRID(")
+                    .push_bind_unseparated(wkb::Encode(coordinates))
+                    .push_unseparated(", 4326)")
+                    .push_bind(location.timestamp);
+            });
+
+            query.push(
+                ") AS v (driver_id, location, timestamp) \
+                JOIN drivers d ON d.id = v.driver_id CROSS JOIN \
+                jsonb_array_elements_text(COALESCE(d.data->'status'->'trip_ids', jsonb_build_array(d.data->'status'->'trip_id'))) AS t (trip_id) \
+                WHERE d.status IN ('assigned', 'pooled')",
+            );
+
+            conn.execute(query.build()).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Stops available drivers whose location expired longer than the grace period ago, as their
+    /// app has most likely stopped reporting, and notifies them that they were deactivated.
+    /// Drivers who have not reported a location since they started are stale once the grace
+    /// period has passed since they started.
+    #[tracing::instrument(skip(self))]
+    pub async fn deactivate_stale_drivers(&self) -> Result<(), Error> {
+        let cutoff = Utc::now() - Duration::seconds(self.stale_location_grace);
+
+        let driver_ids: Vec<(Uuid,)> = sqlx::query_as(
+            "SELECT d.id FROM drivers d JOIN driver_locations l ON d.id = l.driver_id WHERE d.status = 'available' AND GREATEST(l.expiry, d.started_at) < $1",
         )
+        .bind(cutoff)
+        .fetch_all(&self.pool)
         .await?;
 
+        for (driver_id,) in driver_ids.into_iter() {
+            let mut conn = self.pool.acquire().await?;
+            let mut tx = conn.begin().await?;
+
+            let mut driver = fetch_driver_for_update(&mut tx, &driver_id).await?;
+
+            // the driver may have reported a location or taken a trip in the meantime
+            let is_stale = sqlx::query(
+                "SELECT l.driver_id FROM driver_locations l JOIN drivers d ON d.id = l.driver_id WHERE l.driver_id = $1 AND GREATEST(l.expiry, d.started_at) < $2 FOR UPDATE OF l",
+            )
+            .bind(driver_id)
+            .bind(cutoff)
+            .fetch_optional(&mut tx)
+            .await?
+            .is_some();
+
+            if !is_stale || !driver.is_available() {
+                continue;
+            }
+
+            driver.stop()?;
+
+            update_driver(&mut tx, &driver).await?;
+            insert_event(&mut tx, &DomainEvent::DriverDeactivated { driver_id }).await?;
+
+            tx.commit().await?;
+
+            tracing::info!("deactivated driver {} with a stale location", driver_id);
+        }
+
         Ok(())
     }
 }
+
+async fn insert_location_flags(
+    conn: &mut PoolConnection<Database>,
+    flags: &[LocationFlag],
+) -> Result<(), Error> {
+    for flag in flags.iter() {
+        conn.execute(
+            sqlx::query(
+                "INSERT INTO driver_location_flags (driver_id, timestamp, data) VALUES ($1, $2, $3)",
+            )
+            .bind(flag.driver_id)
+            .bind(flag.timestamp)
+            .bind(Json(flag)),
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Checks the valid samples of a batch for signs of spoofing in the order they were recorded.
+/// Invalid samples are skipped so that a sample timestamped far in the future cannot become the
+/// last one of the track and exempt every later sample from the checks.
+fn flag_samples(
+    config: &SpoofingConfig,
+    track: &mut Track,
+    driver_id: Uuid,
+    samples: &[LocationSample],
+    now: DateTime<Utc>,
+) -> Vec<LocationFlag> {
+    let mut samples: Vec<&LocationSample> = samples
+        .iter()
+        .filter(|sample| is_valid_sample(sample, now))
+        .collect();
+
+    samples.sort_by_key(|sample| sample.timestamp);
+
+    let mut flags = vec![];
+
+    for sample in samples.into_iter() {
+        for kind in config.check(track, sample) {
+            tracing::warn!("flagged location of driver {}: {:?}", driver_id, kind);
+
+            flags.push(LocationFlag {
+                driver_id,
+                kind,
+                coordinates: sample.coordinates.clone(),
+                timestamp: sample.timestamp,
+            });
+        }
+    }
+
+    flags
+}
+
+/// Returns whether a sample is neither expired, timestamped too far in the future nor out of
+/// range.
+fn is_valid_sample(sample: &LocationSample, now: DateTime<Utc>) -> bool {
+    sample.timestamp > now - Duration::seconds(LOCATION_TTL_SECONDS)
+        && sample.timestamp < now + Duration::seconds(MAX_CLOCK_SKEW_SECONDS)
+        && sample.coordinates.is_valid()
+}
+
+/// Returns the samples of a batch as locations of the driver, discarding invalid samples.
+fn valid_samples(driver_id: Uuid, samples: Vec<LocationSample>) -> Vec<DriverLocation> {
+    let now = Utc::now();
+
+    samples
+        .into_iter()
+        .filter(|sample| is_valid_sample(sample, now))
+        .map(|sample| DriverLocation {
+            driver_id,
+            coordinates: sample.coordinates,
+            timestamp: sample.timestamp,
+        })
+        .collect()
+}
+
+/// Keeps the location as the pending location of its driver unless a more recent one is already
+/// pending. Returns whether the location was kept.
+fn coalesce(pending: &mut HashMap<Uuid, DriverLocation>, location: DriverLocation) -> bool {
+    match pending.get(&location.driver_id) {
+        Some(current) if current.timestamp >= location.timestamp => false,
+        _ => {
+            pending.insert(location.driver_id, location);
+            true
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::entities::LocationFlagKind;
+
+    fn sample(lat: f64, seconds_ago: i64) -> LocationSample {
+        LocationSample {
+            coordinates: Coordinates { lat, lng: 0.0 },
+            timestamp: Utc::now() - Duration::seconds(seconds_ago),
+            is_mock: false,
+        }
+    }
+
+    #[test]
+    fn valid_samples_test() {
+        let driver_id = Uuid::new_v4();
+
+        assert!(valid_samples(driver_id, vec![]).is_empty());
+        assert!(valid_samples(driver_id, vec![sample(1.0, 120)]).is_empty());
+        assert!(valid_samples(driver_id, vec![sample(1.0, -60)]).is_empty());
+        assert!(valid_samples(driver_id, vec![sample(91.0, 10)]).is_empty());
+
+        let locations = valid_samples(
+            driver_id,
+            vec![sample(1.0, 10), sample(2.0, 120), sample(3.0, 20)],
+        );
+        assert_eq!(locations.len(), 2);
+        assert!(locations
+            .iter()
+            .all(|location| location.driver_id == driver_id));
+    }
+
+    #[test]
+    fn coalesce_test() {
+        let driver_id = Uuid::new_v4();
+        let mut pending = HashMap::new();
+
+        let older = valid_samples(driver_id, vec![sample(1.0, 10)]).remove(0);
+        let newer = valid_samples(driver_id, vec![sample(2.0, 5)]).remove(0);
+
+        assert!(coalesce(&mut pending, newer));
+        assert!(!coalesce(&mut pending, older));
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[&driver_id].coordinates.lat, 2.0);
+    }
+
+    #[test]
+    fn flag_samples_test() {
+        let config = SpoofingConfig::default();
+        let driver_id = Uuid::new_v4();
+        let now = Utc::now();
+        let mut track = Track::default();
+
+        let future = LocationSample {
+            timestamp: now + Duration::days(365 * 50),
+            ..sample(0.0, 0)
+        };
+
+        assert!(flag_samples(
+            &config,
+            &mut track,
+            driver_id,
+            &[sample(0.0, 30), future],
+            now
+        )
+        .is_empty());
+
+        // roughly 11 km in 10 s is still flagged after the future sample
+        let flags = flag_samples(&config, &mut track, driver_id, &[sample(0.1, 20)], now);
+        assert!(matches!(
+            flags.as_slice(),
+            [LocationFlag {
+                kind: LocationFlagKind::ImplausibleSpeed { .. },
+                ..
+            }]
+        ));
+    }
+}
@@ -1,16 +1,23 @@
-use super::Engine;
+use super::helpers::fetch_driver_stats;
+use super::matching::solve;
+use super::surge::geohash;
+use super::{DispatchConfig, Engine};
+
+use std::collections::{BTreeMap, HashSet};
 
 use async_trait::async_trait;
+use chrono::Utc;
 use geo_types::Geometry;
 use geozero::wkb;
-use sqlx::{Executor, Row};
+use sqlx::{types::Json, Executor, Row};
 use uuid::Uuid;
 
 use crate::{
     api::DriverSearchAPI,
     auth::User,
-    entities::{Driver, Trip},
+    entities::{Coordinates, Driver, DriverStats, Trip, VehicleClass},
     error::Error,
+    external::mapbox::{self, Leg},
 };
 
 #[async_trait]
@@ -24,33 +31,50 @@ impl DriverSearchAPI for Engine {
         let trip_distance = trip.route.distance;
         let search_radius = 2000.0;
 
-        // fetch potential driver ids for trip
+        // locations are stored as (lat, lng) points, so they are flipped to measure distances in
+        // meters. the straight-line distance is a lower bound of the routed distance, so drivers
+        // outside the radius or whose fare exceeds the max fare even then are ruled out. drivers
+        // without a registered vehicle are taken to drive an economy vehicle. the max fare of a
+        // pooled trip has the pooling discount taken off, unlike the fares drivers charge. drivers
+        // are charged the tariff in markets priced with one, whatever their rates
+        let max_fare = match (self.pricing.tariff(&trip.route), trip.is_pooled) {
+            (Some(_), _) => f64::INFINITY,
+            (None, true) => self.pooling.fare_limit(trip.max_fare),
+            (None, false) => trip.max_fare,
+        };
+
         let query = "
             SELECT
                 d.id AS driver_id,
-                ST_Distance(l.location, ST_SetSRID($1, 4326)) as distance
+                ST_X(l.location) AS lat,
+                ST_Y(l.location) AS lng
             FROM
                 drivers d
                 LEFT JOIN driver_rates r ON d.id = r.driver_id
                 LEFT JOIN driver_locations l ON d.id = l.driver_id
-                LEFT JOIN driver_priorities p ON d.id = p.driver_id
                 LEFT JOIN trip_rejections tr ON tr.trip_id = $5 AND d.id = tr.driver_id
+                LEFT JOIN vehicles v ON d.id = v.driver_id
+                CROSS JOIN LATERAL (
+                    SELECT ST_Distance(
+                        ST_FlipCoordinates(l.location)::geography,
+                        ST_FlipCoordinates(ST_SetSRID($1, 4326))::geography
+                    ) AS distance
+                ) s
             WHERE
                 d.status = 'available'
                 AND tr.driver_id IS NULL
+                AND COALESCE(v.class, 'economy') = $8
                 AND r.rate IS NOT NULL
                 AND l.location IS NOT NULL
                 AND l.expiry > now()
-                AND ST_DWithin(l.location, ST_SetSRID($1, 4326), $3)
-                AND
-                    GREATEST(
-                        r.min_fare, r.rate * (
-                            ST_Distance(l.location, ST_SetSRID($1, 4326)) + $2
-                        )
-                    ) <= $4
+                AND s.distance <= $3
+                AND GREATEST(r.min_fare, r.rate * (s.distance + $2)) <= $4
+                AND ($7::TIMESTAMPTZ IS NULL OR NOT EXISTS (
+                    SELECT 1 FROM driver_location_flags f WHERE f.driver_id = d.id AND f.timestamp > $7
+                ))
             ORDER BY
-                p.priority ASC,
-                ST_Distance(l.location, ST_SetSRID($1, 4326)) ASC
+                s.distance ASC
+            LIMIT $6
         ";
 
         tracing::info!("fetching potential drivers...");
@@ -62,20 +86,369 @@ impl DriverSearchAPI for Engine {
                     .bind(wkb::Encode(origin_location.clone()))
                     .bind(trip_distance)
                     .bind(search_radius)
-                    .bind(trip.max_fare)
-                    .bind(&trip.id),
+                    .bind(max_fare)
+                    .bind(&trip.id)
+                    .bind(self.eta.max_candidates)
+                    .bind(self.spoofing.withhold_since(Utc::now()))
+                    .bind(trip.vehicle_class.name()),
             )
             .await?;
 
-        let mut fares = vec![];
+        let mut candidates = vec![];
 
         for result in results.iter() {
             let driver_id: Uuid = result.try_get("driver_id")?;
-            let fare: f64 = result.try_get("fare")?;
+            let coordinates = Coordinates {
+                lat: result.try_get("lat")?,
+                lng: result.try_get("lng")?,
+            };
+
+            candidates.push((driver_id, coordinates));
+        }
+
+        let driver_ids: Vec<Uuid> = candidates.iter().map(|(driver_id, _)| *driver_id).collect();
+        let stats = fetch_driver_stats(&mut conn, &driver_ids, self.stats_window).await?;
+
+        let positions: Vec<Coordinates> = candidates
+            .iter()
+            .map(|(_, coordinates)| coordinates.clone())
+            .collect();
+
+        let legs = self
+            .pickup_legs(&positions, &trip.route.origin.coordinates)
+            .await;
+
+        let now = Utc::now();
+
+        let mut drivers: Vec<_> = candidates
+            .into_iter()
+            .zip(legs)
+            .map(|((driver_id, _), leg)| {
+                let score = self.scoring.score(&stats[&driver_id], leg.distance, now);
+                (driver_id, score, leg)
+            })
+            .collect();
+
+        drivers.sort_by(|a, b| b.1.total.total_cmp(&a.1.total));
+
+        for (driver_id, score, leg) in drivers.iter() {
+            tracing::info!(
+                "ranked driver {} for trip {} with {:?} at {:.0} s from pickup",
+                driver_id,
+                trip.id,
+                score,
+                leg.duration
+            );
+        }
+
+        Ok(drivers
+            .into_iter()
+            .map(|(driver_id, _, leg)| (driver_id, leg.distance))
+            .collect())
+    }
+}
+
+/// An available driver considered by the batch matcher.
+struct Candidate {
+    id: Uuid,
+    min_fare: f64,
+    rate: f64,
+    vehicle_class: VehicleClass,
+    stats: DriverStats,
+    coordinates: Coordinates,
+}
+
+impl Engine {
+    /// Returns the driving leg from each position to the destination, served from the cache of
+    /// recently routed legs where possible. Legs are estimated from the straight-line distance
+    /// when routing is disabled or fails.
+    #[tracing::instrument(skip(self))]
+    pub(super) async fn pickup_legs(
+        &self,
+        positions: &[Coordinates],
+        destination: &Coordinates,
+    ) -> Vec<Leg> {
+        let now = Utc::now();
+
+        let mut legs: Vec<Option<Leg>> = match self.eta.enabled {
+            true => {
+                let mut cache = self.eta_cache.lock().unwrap();
+                cache.prune(now);
+
+                positions
+                    .iter()
+                    .map(|position| cache.get(&self.eta.cache_key(position, destination), now))
+                    .collect()
+            }
+            false => vec![None; positions.len()],
+        };
+
+        if self.eta.enabled {
+            let missing: Vec<usize> = (0..positions.len())
+                .filter(|index| legs[*index].is_none())
+                .collect();
+
+            for chunk in missing.chunks(mapbox::MAX_MATRIX_SOURCES) {
+                let sources: Vec<Coordinates> = chunk
+                    .iter()
+                    .map(|index| positions[*index].clone())
+                    .collect();
+
+                let routed = match mapbox::find_legs(&sources, destination).await {
+                    Ok(routed) => routed,
+                    Err(err) => {
+                        tracing::warn!("failed to route pickup legs, estimating: {:?}", err);
+                        continue;
+                    }
+                };
+
+                let mut cache = self.eta_cache.lock().unwrap();
+
+                for (index, leg) in chunk.iter().zip(routed) {
+                    if let Some(leg) = leg {
+                        let key = self.eta.cache_key(&positions[*index], destination);
+                        cache.insert(key, leg.clone(), self.eta.cache_ttl, now);
+
+                        legs[*index] = Some(leg);
+                    }
+                }
+            }
+        }
+
+        legs.into_iter()
+            .zip(positions.iter())
+            .map(|(leg, position)| leg.unwrap_or_else(|| self.eta.estimate(position, destination)))
+            .collect()
+    }
+
+    /// Matches all searching trips with available drivers, zone by zone, by solving the
+    /// assignment problem over the cost of every pair instead of serving each trip greedily.
+    /// Matched drivers are then requested as usual and may still reject the trip.
+    #[tracing::instrument(skip(self))]
+    pub async fn match_trips(&self) -> Result<(), Error> {
+        let batch = match &self.dispatch {
+            DispatchConfig::Batch(batch) => batch,
+            _ => return Ok(()),
+        };
+
+        let user = User::new_system_user();
+        let now = Utc::now();
+        let mut conn = self.pool.acquire().await?;
+
+        let results = conn
+            .fetch_all(sqlx::query(
+                "SELECT data FROM trips WHERE status = 'searching'",
+            ))
+            .await?;
+
+        let mut zones: BTreeMap<String, Vec<Trip>> = BTreeMap::new();
+
+        for result in results.iter() {
+            let Json(trip): Json<Trip> = result.try_get("data")?;
+            let zone = geohash(&trip.route.origin.coordinates, batch.precision);
+
+            zones.entry(zone).or_default().push(trip);
+        }
+
+        if zones.is_empty() {
+            return Ok(());
+        }
+
+        let query = "
+            SELECT
+                d.id,
+                r.min_fare::FLOAT8 AS min_fare,
+                r.rate::FLOAT8 AS rate,
+                COALESCE(v.class, 'economy') AS vehicle_class,
+                ST_X(l.location) AS lat,
+                ST_Y(l.location) AS lng
+            FROM
+                drivers d
+                JOIN driver_rates r ON d.id = r.driver_id
+                JOIN driver_locations l ON d.id = l.driver_id
+                LEFT JOIN vehicles v ON d.id = v.driver_id
+            WHERE
+                d.status = 'available'
+                AND r.rate IS NOT NULL
+                AND l.expiry > now()
+                AND ($1::TIMESTAMPTZ IS NULL OR NOT EXISTS (
+                    SELECT 1 FROM driver_location_flags f WHERE f.driver_id = d.id AND f.timestamp > $1
+                ))
+        ";
+
+        let results = conn
+            .fetch_all(sqlx::query(query).bind(self.spoofing.withhold_since(now)))
+            .await?;
+
+        let mut driver_ids = vec![];
+
+        for result in results.iter() {
+            driver_ids.push(result.try_get("id")?);
+        }
+
+        let mut stats = fetch_driver_stats(&mut conn, &driver_ids, self.stats_window).await?;
+        let mut candidates = vec![];
+
+        for result in results.iter() {
+            let id: Uuid = result.try_get("id")?;
+            let min_fare: Option<f64> = result.try_get("min_fare")?;
+            let vehicle_class: String = result.try_get("vehicle_class")?;
+
+            candidates.push(Candidate {
+                id,
+                min_fare: min_fare.unwrap_or(0.0),
+                rate: result.try_get("rate")?,
+                // drivers without a registered vehicle are taken to drive an economy vehicle
+                vehicle_class: VehicleClass::from_name(&vehicle_class).unwrap_or_default(),
+                stats: stats.remove(&id).unwrap_or_default(),
+                coordinates: Coordinates {
+                    lat: result.try_get("lat")?,
+                    lng: result.try_get("lng")?,
+                },
+            });
+        }
+
+        let trip_ids: Vec<Uuid> = zones.values().flatten().map(|trip| trip.id).collect();
+
+        let rejections: HashSet<(Uuid, Uuid)> = sqlx::query_as(
+            "SELECT trip_id, driver_id FROM trip_rejections WHERE trip_id = ANY($1)",
+        )
+        .bind(&trip_ids)
+        .fetch_all(&mut conn)
+        .await?
+        .into_iter()
+        .collect();
+
+        // drivers are matched in at most one zone per run
+        let mut matched = HashSet::new();
+
+        for (zone, trips) in zones.iter() {
+            let drivers: Vec<&Candidate> = candidates
+                .iter()
+                .filter(|driver| !matched.contains(&driver.id))
+                .filter(|driver| {
+                    trips.iter().any(|trip| {
+                        trip.vehicle_class == driver.vehicle_class
+                            && trip.route.origin.coordinates.distance(&driver.coordinates)
+                                <= batch.search_radius
+                    })
+                })
+                .collect();
+
+            // pickup legs are only routed for the drivers of the class within the radius of each
+            // trip
+            let mut legs: Vec<Vec<Option<Leg>>> = vec![];
+
+            for trip in trips.iter() {
+                let origin = &trip.route.origin.coordinates;
+
+                let nearby: Vec<usize> = (0..drivers.len())
+                    .filter(|index| {
+                        drivers[*index].vehicle_class == trip.vehicle_class
+                            && origin.distance(&drivers[*index].coordinates) <= batch.search_radius
+                    })
+                    .collect();
+
+                let positions: Vec<Coordinates> = nearby
+                    .iter()
+                    .map(|index| drivers[*index].coordinates.clone())
+                    .collect();
+
+                let mut row = vec![None; drivers.len()];
+
+                for (index, leg) in nearby
+                    .into_iter()
+                    .zip(self.pickup_legs(&positions, origin).await)
+                {
+                    row[index] = Some(leg);
+                }
+
+                legs.push(row);
+            }
+
+            let costs: Vec<Vec<f64>> = trips
+                .iter()
+                .zip(legs.iter())
+                .map(|(trip, row)| {
+                    drivers
+                        .iter()
+                        .zip(row.iter())
+                        .map(|(driver, leg)| {
+                            let distance = match leg {
+                                Some(leg) => leg.distance,
+                                None => return f64::INFINITY,
+                            };
+
+                            if rejections.contains(&(trip.id, driver.id)) {
+                                return f64::INFINITY;
+                            }
+
+                            let fare = self.adjust_fare(
+                                self.route_fare(
+                                    &trip.route,
+                                    driver.min_fare,
+                                    driver.rate,
+                                    distance,
+                                )
+                                .with_surge(trip.surge_multiplier),
+                                trip.vehicle_class,
+                                trip.is_pooled,
+                            );
+
+                            match fare.total > trip.max_fare {
+                                true => f64::INFINITY,
+                                false => {
+                                    let score = self.scoring.score(&driver.stats, distance, now);
+                                    batch.cost(fare.total, score.total)
+                                }
+                            }
+                        })
+                        .collect()
+                })
+                .collect();
+
+            let assignment = solve(&costs);
+
+            tracing::info!(
+                "matched {} of {} trips in zone {}",
+                assignment.iter().flatten().count(),
+                trips.len(),
+                zone
+            );
+
+            for ((trip, row), column) in trips.iter().zip(legs.iter()).zip(assignment) {
+                let (driver, distance) = match column.and_then(|column| {
+                    row[column]
+                        .as_ref()
+                        .map(|leg| (drivers[column], leg.distance))
+                }) {
+                    Some(pair) => pair,
+                    None => continue,
+                };
+
+                matched.insert(driver.id);
+
+                tracing::info!(
+                    "matched driver {} with trip {} with {:?}",
+                    driver.id,
+                    trip.id,
+                    self.scoring.score(&driver.stats, distance, now)
+                );
 
-            fares.push((driver_id, fare));
+                match self.offer_trip(&user, trip.id, driver.id, distance).await {
+                    Ok(Some(_)) => {}
+                    Ok(None) => tracing::info!(
+                        "driver {} is no longer available for trip {}",
+                        driver.id,
+                        trip.id
+                    ),
+                    Err(err) => {
+                        tracing::warn!("failed to request driver for trip {}: {:?}", trip.id, err)
+                    }
+                }
+            }
         }
 
-        Ok(fares)
+        Ok(())
     }
 }
@@ -0,0 +1,141 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::surge::geohash;
+use crate::{entities::Coordinates, external::mapbox::Leg};
+
+/// Settings of the pickup ETAs drivers are ranked by, with distances in meters, durations in
+/// seconds and the average speed in meters per second.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct EtaConfig {
+    // whether pickup legs are routed by the routing service, otherwise they are estimated
+    pub enabled: bool,
+    // how many of the nearest drivers by straight-line distance are routed
+    pub max_candidates: i64,
+    // geohash precision of the cells positions share cached legs within
+    pub cache_precision: usize,
+    pub cache_ttl: i64,
+    // used to estimate legs when routing is disabled or fails
+    pub average_speed: f64,
+    pub detour_factor: f64,
+}
+
+impl Default for EtaConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_candidates: 20,
+            cache_precision: 7,
+            cache_ttl: 120,
+            average_speed: 8.0,
+            detour_factor: 1.3,
+        }
+    }
+}
+
+impl EtaConfig {
+    /// Estimates the leg between two points from the straight-line distance.
+    pub fn estimate(&self, from: &Coordinates, to: &Coordinates) -> Leg {
+        let distance = from.distance(to) * self.detour_factor;
+
+        Leg {
+            distance,
+            duration: distance / self.average_speed,
+        }
+    }
+
+    /// Returns the key legs between two points are cached by, which is shared by all positions
+    /// within the same pair of cells.
+    pub fn cache_key(&self, from: &Coordinates, to: &Coordinates) -> (String, String) {
+        (
+            geohash(from, self.cache_precision),
+            geohash(to, self.cache_precision),
+        )
+    }
+}
+
+/// Recently routed legs, kept for a limited time as traffic conditions change.
+#[derive(Debug, Default)]
+pub struct EtaCache {
+    entries: HashMap<(String, String), (Leg, DateTime<Utc>)>,
+}
+
+impl EtaCache {
+    pub fn get(&self, key: &(String, String), now: DateTime<Utc>) -> Option<Leg> {
+        match self.entries.get(key) {
+            Some((leg, expires_at)) if *expires_at > now => Some(leg.clone()),
+            _ => None,
+        }
+    }
+
+    pub fn insert(&mut self, key: (String, String), leg: Leg, ttl: i64, now: DateTime<Utc>) {
+        self.entries
+            .insert(key, (leg, now + Duration::seconds(ttl)));
+    }
+
+    /// Drops expired entries.
+    pub fn prune(&mut self, now: DateTime<Utc>) {
+        self.entries.retain(|_, (_, expires_at)| *expires_at > now);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn estimate_test() {
+        let config = EtaConfig::default();
+
+        let leg = config.estimate(
+            &Coordinates { lat: 0.0, lng: 0.0 },
+            &Coordinates {
+                lat: 0.0,
+                lng: 0.01,
+            },
+        );
+
+        // roughly 1112 m in a straight line
+        assert!((leg.distance - 1112.0 * 1.3).abs() < 5.0);
+        assert!((leg.duration - leg.distance / 8.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn cache_test() {
+        let config = EtaConfig::default();
+        let mut cache = EtaCache::default();
+        let now = Utc::now();
+
+        let from = Coordinates {
+            lat: 4.17,
+            lng: 73.51,
+        };
+        let to = Coordinates {
+            lat: 4.18,
+            lng: 73.52,
+        };
+        let leg = Leg {
+            distance: 1500.0,
+            duration: 200.0,
+        };
+
+        cache.insert(config.cache_key(&from, &to), leg.clone(), 60, now);
+
+        // a position a few meters away shares the cached leg
+        let nearby = Coordinates {
+            lat: 4.17001,
+            lng: 73.51001,
+        };
+        assert_eq!(cache.get(&config.cache_key(&nearby, &to), now), Some(leg));
+
+        assert_eq!(cache.get(&config.cache_key(&to, &from), now), None);
+
+        let later = now + Duration::seconds(61);
+        assert_eq!(cache.get(&config.cache_key(&from, &to), later), None);
+
+        cache.prune(later);
+        assert!(cache.entries.is_empty());
+    }
+}
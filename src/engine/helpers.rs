@@ -1,11 +1,19 @@
 use super::Database;
 
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
 use sqlx::{types::Json, Executor, Row, Transaction};
+use tokio::sync::mpsc;
 use uuid::Uuid;
 
 use crate::{
-    entities::{Driver, Passenger, Trip},
-    error::{invalid_input_error, Error},
+    api::Subscription,
+    entities::{
+        Coordinates, DomainEvent, Driver, DriverStats, Passenger, Quote, TracePoint, Trip,
+        TripStatus, TripTrace,
+    },
+    error::{invalid_input_error, invalid_invocation_error, Error},
 };
 
 #[tracing::instrument(skip(tx))]
@@ -22,6 +30,22 @@ pub async fn fetch_trip_for_update(
     Ok(trip)
 }
 
+#[tracing::instrument(skip(tx))]
+pub async fn fetch_quote_for_update(
+    tx: &mut Transaction<'_, Database>,
+    token: &Uuid,
+) -> Result<Quote, Error> {
+    let Json(quote): Json<Quote> = tx
+        .fetch_optional(
+            sqlx::query("SELECT data FROM quotes WHERE token = $1 FOR UPDATE").bind(token),
+        )
+        .await?
+        .ok_or_else(invalid_input_error)?
+        .try_get("data")?;
+
+    Ok(quote)
+}
+
 #[tracing::instrument(skip(tx))]
 pub async fn fetch_driver_for_update(
     tx: &mut Transaction<'_, Database>,
@@ -52,6 +76,26 @@ pub async fn fetch_passenger_for_update(
     Ok(passenger)
 }
 
+/// Fetches the minimum fare and the rate a driver charges.
+#[tracing::instrument(skip(tx))]
+pub async fn fetch_driver_rate(
+    tx: &mut Transaction<'_, Database>,
+    driver_id: &Uuid,
+) -> Result<(f64, f64), Error> {
+    let (min_fare, rate): (Option<f64>, Option<f64>) = sqlx::query_as(
+        "SELECT min_fare::FLOAT8, rate::FLOAT8 FROM driver_rates WHERE driver_id = $1",
+    )
+    .bind(driver_id)
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or_else(invalid_input_error)?;
+
+    Ok((
+        min_fare.unwrap_or(0.0),
+        rate.ok_or_else(invalid_invocation_error)?,
+    ))
+}
+
 #[tracing::instrument(skip(tx))]
 pub async fn update_trip(tx: &mut Transaction<'_, Database>, trip: &Trip) -> Result<(), Error> {
     tx.execute(
@@ -65,6 +109,223 @@ pub async fn update_trip(tx: &mut Transaction<'_, Database>, trip: &Trip) -> Res
     Ok(())
 }
 
+/// Writes a domain event to the outbox, to be published by the relay once the transaction has
+/// committed.
+#[tracing::instrument(skip(tx))]
+pub async fn insert_event(
+    tx: &mut Transaction<'_, Database>,
+    event: &DomainEvent,
+) -> Result<(), Error> {
+    tx.execute(
+        sqlx::query("INSERT INTO outbox (created_at, event) VALUES ($1, $2)")
+            .bind(Utc::now())
+            .bind(Json(event)),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Something a driver did that counts towards their statistics.
+#[derive(Debug)]
+pub enum DriverStat {
+    Offer,
+    Acceptance,
+    Rejection,
+    Expiration,
+    Cancellation,
+    Arrival { is_late: bool },
+    Rating(i32),
+    Completion,
+}
+
+/// Counts a driver stat towards the day it happened on.
+#[tracing::instrument(skip(tx))]
+pub async fn record_driver_stat(
+    tx: &mut Transaction<'_, Database>,
+    driver_id: Uuid,
+    stat: DriverStat,
+) -> Result<(), Error> {
+    let delta = match stat {
+        DriverStat::Offer => DriverStats {
+            offers: 1,
+            ..Default::default()
+        },
+        DriverStat::Acceptance => DriverStats {
+            acceptances: 1,
+            ..Default::default()
+        },
+        DriverStat::Rejection => DriverStats {
+            rejections: 1,
+            ..Default::default()
+        },
+        DriverStat::Expiration => DriverStats {
+            expirations: 1,
+            ..Default::default()
+        },
+        DriverStat::Cancellation => DriverStats {
+            cancellations: 1,
+            ..Default::default()
+        },
+        DriverStat::Arrival { is_late } => DriverStats {
+            arrivals: 1,
+            late_arrivals: is_late as i32,
+            ..Default::default()
+        },
+        DriverStat::Rating(rating) => DriverStats {
+            ratings: 1,
+            rating_total: rating as f64,
+            ..Default::default()
+        },
+        DriverStat::Completion => DriverStats {
+            last_trip_at: Some(Utc::now()),
+            ..Default::default()
+        },
+    };
+
+    tx.execute(
+        sqlx::query("
+            INSERT INTO driver_stats (driver_id, day, offers, acceptances, rejections, expirations, cancellations, arrivals, late_arrivals, ratings, rating_total, last_trip_at)
+            VALUES ($1, current_date, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+            ON CONFLICT (driver_id, day) DO UPDATE SET
+                offers = driver_stats.offers + EXCLUDED.offers,
+                acceptances = driver_stats.acceptances + EXCLUDED.acceptances,
+                rejections = driver_stats.rejections + EXCLUDED.rejections,
+                expirations = driver_stats.expirations + EXCLUDED.expirations,
+                cancellations = driver_stats.cancellations + EXCLUDED.cancellations,
+                arrivals = driver_stats.arrivals + EXCLUDED.arrivals,
+                late_arrivals = driver_stats.late_arrivals + EXCLUDED.late_arrivals,
+                ratings = driver_stats.ratings + EXCLUDED.ratings,
+                rating_total = driver_stats.rating_total + EXCLUDED.rating_total,
+                last_trip_at = COALESCE(EXCLUDED.last_trip_at, driver_stats.last_trip_at)
+        ")
+        .bind(driver_id)
+        .bind(delta.offers)
+        .bind(delta.acceptances)
+        .bind(delta.rejections)
+        .bind(delta.expirations)
+        .bind(delta.cancellations)
+        .bind(delta.arrivals)
+        .bind(delta.late_arrivals)
+        .bind(delta.ratings)
+        .bind(delta.rating_total)
+        .bind(delta.last_trip_at),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Fetches the statistics of drivers over the last `window` days, including today. Drivers with
+/// nothing recorded in the window have all counts at zero.
+#[tracing::instrument(skip(executor))]
+pub async fn fetch_driver_stats<'c, E>(
+    executor: E,
+    driver_ids: &[Uuid],
+    window: i32,
+) -> Result<HashMap<Uuid, DriverStats>, Error>
+where
+    E: Executor<'c, Database = Database>,
+{
+    let results = executor
+        .fetch_all(
+            sqlx::query(
+                "
+                SELECT
+                    driver_id,
+                    SUM(offers)::INT4 AS offers,
+                    SUM(acceptances)::INT4 AS acceptances,
+                    SUM(rejections)::INT4 AS rejections,
+                    SUM(expirations)::INT4 AS expirations,
+                    SUM(cancellations)::INT4 AS cancellations,
+                    SUM(arrivals)::INT4 AS arrivals,
+                    SUM(late_arrivals)::INT4 AS late_arrivals,
+                    SUM(ratings)::INT4 AS ratings,
+                    SUM(rating_total)::FLOAT8 AS rating_total,
+                    MAX(last_trip_at) AS last_trip_at
+                FROM driver_stats
+                WHERE driver_id = ANY($1) AND day > current_date - $2
+                GROUP BY driver_id
+            ",
+            )
+            .bind(driver_ids)
+            .bind(window),
+        )
+        .await?;
+
+    let mut stats: HashMap<Uuid, DriverStats> = driver_ids
+        .iter()
+        .map(|driver_id| {
+            let empty = DriverStats {
+                driver_id: *driver_id,
+                window,
+                ..Default::default()
+            };
+
+            (*driver_id, empty)
+        })
+        .collect();
+
+    for result in results.iter() {
+        let driver_id: Uuid = result.try_get("driver_id")?;
+
+        stats.insert(
+            driver_id,
+            DriverStats {
+                driver_id,
+                window,
+                offers: result.try_get("offers")?,
+                acceptances: result.try_get("acceptances")?,
+                rejections: result.try_get("rejections")?,
+                expirations: result.try_get("expirations")?,
+                cancellations: result.try_get("cancellations")?,
+                arrivals: result.try_get("arrivals")?,
+                late_arrivals: result.try_get("late_arrivals")?,
+                ratings: result.try_get("ratings")?,
+                rating_total: result.try_get("rating_total")?,
+                last_trip_at: result.try_get("last_trip_at")?,
+            },
+        );
+    }
+
+    Ok(stats)
+}
+
+/// Appends the transition of a trip from its previous status (if any) to its current status to
+/// the trip history.
+#[tracing::instrument(skip(tx))]
+pub async fn insert_trip_event(
+    tx: &mut Transaction<'_, Database>,
+    actor_id: &Uuid,
+    from_status: Option<&TripStatus>,
+    trip: &Trip,
+) -> Result<(), Error> {
+    tx.execute(
+        sqlx::query("INSERT INTO trip_events (trip_id, actor_id, from_status, to_status, timestamp, payload) VALUES ($1, $2, $3, $4, $5, $6)")
+            .bind(trip.id)
+            .bind(actor_id)
+            .bind(from_status.map(|status| status.name()))
+            .bind(trip.status.name())
+            .bind(Utc::now())
+            .bind(Json(&trip.status)),
+    )
+    .await?;
+
+    Ok(())
+}
+
+#[tracing::instrument(skip(tx))]
+pub async fn update_quote(tx: &mut Transaction<'_, Database>, quote: &Quote) -> Result<(), Error> {
+    tx.execute(
+        sqlx::query("UPDATE quotes SET data = $2 WHERE token = $1")
+            .bind(quote.token)
+            .bind(Json(quote)),
+    )
+    .await?;
+
+    Ok(())
+}
+
 #[tracing::instrument(skip(tx))]
 pub async fn update_driver(
     tx: &mut Transaction<'_, Database>,
@@ -96,3 +357,48 @@ pub async fn update_passenger(
 
     Ok(())
 }
+
+/// Fetches the trace of a trip, optionally only the points recorded since the given time.
+#[tracing::instrument(skip(executor))]
+pub async fn fetch_trip_trace<'c, E>(
+    executor: E,
+    trip_id: &Uuid,
+    since: Option<DateTime<Utc>>,
+) -> Result<TripTrace, Error>
+where
+    E: Executor<'c, Database = Database>,
+{
+    let results = executor
+        .fetch_all(
+            sqlx::query("SELECT ST_X(location) AS lat, ST_Y(location) AS lng, timestamp FROM trip_traces WHERE trip_id = $1 AND ($2::TIMESTAMPTZ IS NULL OR timestamp >= $2) ORDER BY timestamp ASC, id ASC")
+                .bind(trip_id)
+                .bind(since),
+        )
+        .await?;
+
+    let mut points = vec![];
+
+    for result in results.iter() {
+        points.push(TracePoint {
+            coordinates: Coordinates {
+                lat: result.try_get("lat")?,
+                lng: result.try_get("lng")?,
+            },
+            timestamp: result.try_get("timestamp")?,
+        });
+    }
+
+    Ok(TripTrace {
+        trip_id: *trip_id,
+        points,
+    })
+}
+
+/// Turns the receiving end of a channel into a subscription that ends once all senders are
+/// dropped.
+pub fn subscription<T: Send + 'static>(receiver: mpsc::Receiver<T>) -> Subscription<T> {
+    Box::pin(futures::stream::unfold(
+        receiver,
+        |mut receiver| async move { receiver.recv().await.map(|item| (item, receiver)) },
+    ))
+}
@@ -0,0 +1,83 @@
+use super::{DispatchConfig, Engine};
+
+use std::future::Future;
+use std::sync::Arc;
+
+use tokio::time::{interval, Duration};
+
+use crate::error::Error;
+
+impl Engine {
+    /// Spawns the periodic background jobs of the engine onto the tokio runtime.
+    pub fn spawn_jobs(self: &Arc<Self>) {
+        self.spawn_periodic(
+            "dispatch_waiting_trips",
+            Duration::from_secs(10),
+            |engine| async move { engine.dispatch_waiting_trips().await },
+        );
+
+        if let DispatchConfig::Batch(batch) = &self.dispatch {
+            self.spawn_periodic(
+                "match_trips",
+                Duration::from_secs(batch.interval),
+                |engine| async move { engine.match_trips().await },
+            );
+        }
+
+        self.spawn_periodic(
+            "dispatch_scheduled_trips",
+            Duration::from_secs(30),
+            |engine| async move { engine.dispatch_scheduled_trips().await },
+        );
+
+        self.spawn_periodic(
+            "relay_events",
+            Duration::from_secs(1),
+            |engine| async move { engine.relay_events().await },
+        );
+
+        self.spawn_periodic(
+            "flush_driver_locations",
+            Duration::from_secs(1),
+            |engine| async move { engine.flush_driver_locations().await },
+        );
+
+        self.spawn_periodic(
+            "deactivate_stale_drivers",
+            Duration::from_secs(30),
+            |engine| async move { engine.deactivate_stale_drivers().await },
+        );
+
+        self.spawn_periodic(
+            "monitor_trips",
+            Duration::from_secs(15),
+            |engine| async move { engine.monitor_trips().await },
+        );
+
+        self.spawn_periodic(
+            "update_surge",
+            Duration::from_secs(30),
+            |engine| async move { engine.update_surge().await },
+        );
+    }
+
+    fn spawn_periodic<F, Fut>(self: &Arc<Self>, name: &'static str, period: Duration, job: F)
+    where
+        F: Fn(Arc<Engine>) -> Fut + Send + 'static,
+        Fut: Future<Output = Result<(), Error>> + Send,
+    {
+        let engine = self.clone();
+
+        tokio::spawn(async move {
+            let mut interval = interval(period);
+
+            loop {
+                interval.tick().await;
+
+                if let Err(err) = job(engine.clone()).await {
+                    tracing::error!("job {} failed: {:?}", name, err);
+                }
+            }
+        });
+    }
+}
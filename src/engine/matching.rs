@@ -0,0 +1,196 @@
+use serde::{Deserialize, Serialize};
+
+/// Settings of the batch matcher, with distances in meters. The cost of matching a trip with a
+/// driver is the fare less the score of the driver, each weighted by how much a unit is worth.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BatchConfig {
+    // how often the matcher runs, in seconds
+    pub interval: u64,
+    // geohash precision of the zones trips are matched in
+    pub precision: usize,
+    pub search_radius: f64,
+    pub fare_weight: f64,
+    pub score_weight: f64,
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        Self {
+            interval: 5,
+            precision: 5,
+            search_radius: 2000.0,
+            fare_weight: 1.0,
+            score_weight: 10.0,
+        }
+    }
+}
+
+impl BatchConfig {
+    pub fn cost(&self, fare: f64, score: f64) -> f64 {
+        self.fare_weight * fare - self.score_weight * score
+    }
+}
+
+/// Solves the assignment problem for a cost matrix of trips by drivers using the Hungarian
+/// algorithm, returning the driver assigned to each trip. Pairs with a non-finite cost are never
+/// assigned, so trips may be left unassigned when there are too few feasible drivers.
+pub fn solve(costs: &[Vec<f64>]) -> Vec<Option<usize>> {
+    let rows = costs.len();
+    let columns = costs.first().map_or(0, |row| row.len());
+
+    if rows == 0 || columns == 0 {
+        return vec![None; rows];
+    }
+
+    // infeasible pairs are given a cost higher than any feasible assignment as a whole
+    let infeasible = 1.0
+        + costs
+            .iter()
+            .flatten()
+            .filter(|cost| cost.is_finite())
+            .map(|cost| cost.abs())
+            .sum::<f64>();
+
+    // the algorithm requires no more rows than columns
+    let transposed = rows > columns;
+    let (n, m) = match transposed {
+        true => (columns, rows),
+        false => (rows, columns),
+    };
+
+    let cost = |i: usize, j: usize| {
+        let cost = match transposed {
+            true => costs[j][i],
+            false => costs[i][j],
+        };
+
+        match cost.is_finite() {
+            true => cost,
+            false => infeasible,
+        }
+    };
+
+    // potentials of rows and columns, and the row matched to each column, indexed from 1 with 0
+    // as a sentinel
+    let mut u = vec![0.0; n + 1];
+    let mut v = vec![0.0; m + 1];
+    let mut matched = vec![0; m + 1];
+    let mut way = vec![0; m + 1];
+
+    for i in 1..=n {
+        matched[0] = i;
+
+        let mut j0 = 0;
+        let mut min = vec![f64::INFINITY; m + 1];
+        let mut used = vec![false; m + 1];
+
+        loop {
+            used[j0] = true;
+
+            let i0 = matched[j0];
+            let mut delta = f64::INFINITY;
+            let mut j1 = 0;
+
+            for j in 1..=m {
+                if used[j] {
+                    continue;
+                }
+
+                let reduced = cost(i0 - 1, j - 1) - u[i0] - v[j];
+
+                if reduced < min[j] {
+                    min[j] = reduced;
+                    way[j] = j0;
+                }
+
+                if min[j] < delta {
+                    delta = min[j];
+                    j1 = j;
+                }
+            }
+
+            for j in 0..=m {
+                if used[j] {
+                    u[matched[j]] += delta;
+                    v[j] -= delta;
+                } else {
+                    min[j] -= delta;
+                }
+            }
+
+            j0 = j1;
+
+            if matched[j0] == 0 {
+                break;
+            }
+        }
+
+        // augment along the alternating path
+        loop {
+            let j1 = way[j0];
+            matched[j0] = matched[j1];
+            j0 = j1;
+
+            if j0 == 0 {
+                break;
+            }
+        }
+    }
+
+    let mut assignment = vec![None; rows];
+
+    for (j, &i) in matched.iter().enumerate().skip(1) {
+        if i == 0 {
+            continue;
+        }
+
+        let (row, column) = match transposed {
+            true => (j - 1, i - 1),
+            false => (i - 1, j - 1),
+        };
+
+        if costs[row][column].is_finite() {
+            assignment[row] = Some(column);
+        }
+    }
+
+    assignment
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const X: f64 = f64::INFINITY;
+
+    #[test]
+    fn solve_test() {
+        // greedily serving the first trip with its nearest driver would cost 1 + 100
+        let costs = vec![vec![1.0, 2.0], vec![1.0, 100.0]];
+        assert_eq!(solve(&costs), vec![Some(1), Some(0)]);
+
+        let costs = vec![
+            vec![4.0, 1.0, 3.0],
+            vec![2.0, 0.0, 5.0],
+            vec![3.0, 2.0, 2.0],
+        ];
+        assert_eq!(solve(&costs), vec![Some(1), Some(0), Some(2)]);
+
+        // more drivers than trips, and more trips than drivers
+        let costs = vec![vec![5.0, 1.0, 9.0]];
+        assert_eq!(solve(&costs), vec![Some(1)]);
+
+        let costs = vec![vec![5.0], vec![1.0], vec![9.0]];
+        assert_eq!(solve(&costs), vec![None, Some(0), None]);
+
+        // infeasible pairs are left unassigned rather than forced
+        let costs = vec![vec![1.0, X], vec![2.0, X]];
+        assert_eq!(solve(&costs), vec![Some(0), None]);
+
+        let costs = vec![vec![X, 3.0], vec![1.0, 2.0]];
+        assert_eq!(solve(&costs), vec![Some(1), Some(0)]);
+
+        assert_eq!(solve(&[]), vec![]);
+    }
+}
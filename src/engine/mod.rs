@@ -1,32 +1,92 @@
+mod dispatch;
 mod driver_api;
 mod driver_location_api;
 mod driver_search_api;
+mod eta;
 mod helpers;
+mod jobs;
 mod location_api;
+mod matching;
+mod outbox;
 mod passenger_api;
+mod pooling;
+mod pricing;
 mod quote_api;
 mod route_api;
+mod safety;
+mod safety_api;
+mod scheduling;
+mod scheduling_api;
+mod scoring;
+mod spoofing;
+mod surge;
+mod surge_api;
 mod trip_api;
 
+use std::collections::HashMap;
+use std::env;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, Weak};
+
 use oso::Oso;
+use serde::de::DeserializeOwned;
 use sqlx::{Executor, Pool, Postgres};
+use tokio::sync::broadcast;
+use uuid::Uuid;
 
 use crate::{
     api::API,
     auth::authorizor,
-    error::{unauthorized_error, Error},
+    entities::{DriverLocation, FareAdjustments, FareBreakdown, OutboxEvent, Route, VehicleClass},
+    error::{invalid_input_error, unauthorized_error, Error},
 };
 
+use dispatch::DispatchConfig;
+use eta::{EtaCache, EtaConfig};
+use pooling::PoolingConfig;
+use pricing::{MeteringConfig, PricingConfig};
+use safety::SafetyConfig;
+use scheduling::SchedulingConfig;
+use scoring::ScoringConfig;
+use spoofing::{SpoofingConfig, Track};
+use surge::SurgeConfig;
+
 type Database = Postgres;
 
 pub struct Engine {
+    // the engine itself, shared with the tasks spawned to serve subscriptions
+    this: Weak<Engine>,
     pool: Pool<Database>,
     authorizor: Oso,
+    fare_adjustments: FareAdjustments,
+    pricing: PricingConfig,
+    metering: MeteringConfig,
+    surge: SurgeConfig,
+    safety: SafetyConfig,
+    scheduling: SchedulingConfig,
+    pooling: PoolingConfig,
+    dispatch: DispatchConfig,
+    eta: EtaConfig,
+    scoring: ScoringConfig,
+    // how long quotes may be booked for, in seconds
+    quote_ttl: i64,
+    // how many days driver stats are aggregated over
+    stats_window: i32,
+    // how long after their location expires available drivers are stopped, in seconds
+    stale_location_grace: i64,
+    spoofing: SpoofingConfig,
+    events: broadcast::Sender<OutboxEvent>,
+    locations: broadcast::Sender<DriverLocation>,
+    pending_locations: Mutex<HashMap<Uuid, DriverLocation>>,
+    pending_trace: Mutex<Vec<DriverLocation>>,
+    eta_cache: Mutex<EtaCache>,
+    // the last location each driver reported, to check the next one against
+    location_tracks: Mutex<HashMap<Uuid, Track>>,
 }
 
 impl Engine {
     #[tracing::instrument(name = "Engine::new", skip_all)]
-    pub async fn new(pool: Pool<Database>) -> Result<Self, Error> {
+    pub async fn new(pool: Pool<Database>) -> Result<Arc<Self>, Error> {
         // location service (KV store)
         pool.execute("DROP TABLE IF EXISTS locations CASCADE")
             .await?;
@@ -48,6 +108,18 @@ impl Engine {
         pool.execute("CREATE TABLE trips (id UUID PRIMARY KEY, status VARCHAR NOT NULL, data JSONB NOT NULL)")
             .await?;
 
+        // transactional outbox of domain events
+        pool.execute("DROP TABLE IF EXISTS outbox CASCADE").await?;
+        pool.execute("CREATE TABLE outbox (id BIGSERIAL PRIMARY KEY, created_at TIMESTAMPTZ NOT NULL, event JSONB NOT NULL, published_at TIMESTAMPTZ)")
+            .await?;
+
+        pool.execute("DROP TABLE IF EXISTS trip_events CASCADE")
+            .await?;
+        pool.execute("CREATE TABLE trip_events (id BIGSERIAL PRIMARY KEY, trip_id UUID NOT NULL, actor_id UUID NOT NULL, from_status VARCHAR, to_status VARCHAR NOT NULL, timestamp TIMESTAMPTZ NOT NULL, payload JSONB NOT NULL)")
+            .await?;
+        pool.execute("CREATE INDEX trip_events_trip_id_idx ON trip_events (trip_id, id)")
+            .await?;
+
         pool.execute("DROP TABLE IF EXISTS trip_rejections CASCADE")
             .await?;
         pool.execute("CREATE TABLE trip_rejections (trip_id UUID NOT NULL, driver_id UUID NOT NULL, PRIMARY KEY (trip_id, driver_id))")
@@ -59,7 +131,7 @@ impl Engine {
                 .await?;
 
         pool.execute("DROP TABLE IF EXISTS drivers CASCADE").await?;
-        pool.execute("CREATE TABLE drivers (id UUID PRIMARY KEY, status VARCHAR NOT NULL, data JSONB NOT NULL)")
+        pool.execute("CREATE TABLE drivers (id UUID PRIMARY KEY, status VARCHAR NOT NULL, started_at TIMESTAMPTZ, data JSONB NOT NULL)")
             .await?;
 
         pool.execute("DROP TABLE IF EXISTS driver_rates CASCADE")
@@ -74,21 +146,141 @@ impl Engine {
         pool.execute("CREATE TABLE driver_locations (driver_id UUID PRIMARY KEY, location geometry(Point), expiry TIMESTAMP)")
             .await?;
 
-        pool.execute("DROP TABLE IF EXISTS driver_priorities CASCADE")
+        // locations reported by the assigned driver of each trip
+        pool.execute("DROP TABLE IF EXISTS trip_traces CASCADE")
             .await?;
-        pool.execute(
-            "CREATE TABLE driver_priorities (driver_id UUID PRIMARY KEY, priority INT4 NOT NULL)",
-        )
-        .await?;
+        pool.execute("CREATE TABLE trip_traces (id BIGSERIAL PRIMARY KEY, trip_id UUID NOT NULL, driver_id UUID NOT NULL, location geometry(Point) NOT NULL, timestamp TIMESTAMPTZ NOT NULL)")
+            .await?;
+        pool.execute("CREATE INDEX trip_traces_trip_id_idx ON trip_traces (trip_id, timestamp)")
+            .await?;
+
+        pool.execute("DROP TABLE IF EXISTS safety_alerts CASCADE")
+            .await?;
+        pool.execute("CREATE TABLE safety_alerts (id UUID PRIMARY KEY, trip_id UUID NOT NULL, kind VARCHAR NOT NULL, timestamp TIMESTAMPTZ NOT NULL, data JSONB NOT NULL, UNIQUE (trip_id, kind))")
+            .await?;
+
+        pool.execute("DROP TABLE IF EXISTS surge_cells CASCADE")
+            .await?;
+        pool.execute("CREATE TABLE surge_cells (cell VARCHAR PRIMARY KEY, demand INT4 NOT NULL, supply INT4 NOT NULL, multiplier FLOAT8 NOT NULL, updated_at TIMESTAMPTZ NOT NULL)")
+            .await?;
+
+        pool.execute("DROP TABLE IF EXISTS driver_location_flags CASCADE")
+            .await?;
+        pool.execute("CREATE TABLE driver_location_flags (id BIGSERIAL PRIMARY KEY, driver_id UUID NOT NULL, timestamp TIMESTAMPTZ NOT NULL, data JSONB NOT NULL)")
+            .await?;
+        pool.execute("CREATE INDEX driver_location_flags_driver_id_idx ON driver_location_flags (driver_id, timestamp)")
+            .await?;
+
+        pool.execute("DROP TABLE IF EXISTS driver_stats CASCADE")
+            .await?;
+        pool.execute("CREATE TABLE driver_stats (driver_id UUID NOT NULL, day DATE NOT NULL, offers INT4 NOT NULL, acceptances INT4 NOT NULL, rejections INT4 NOT NULL, expirations INT4 NOT NULL, cancellations INT4 NOT NULL, arrivals INT4 NOT NULL, late_arrivals INT4 NOT NULL, ratings INT4 NOT NULL, rating_total FLOAT8 NOT NULL, last_trip_at TIMESTAMPTZ, PRIMARY KEY (driver_id, day))")
+            .await?;
+
+        pool.execute("DROP TABLE IF EXISTS vehicles CASCADE")
+            .await?;
+        pool.execute("CREATE TABLE vehicles (driver_id UUID PRIMARY KEY, class VARCHAR NOT NULL, data JSONB NOT NULL)")
+            .await?;
+
+        let fare_adjustments = FareAdjustments {
+            booking_fee: env_parse("BOOKING_FEE")?.unwrap_or(0.0),
+            tax_rate: env_parse("TAX_RATE")?.unwrap_or(0.0),
+            // a flat amount taken off every fare before taxes
+            discount: env_parse("DISCOUNT")?.unwrap_or(0.0),
+        };
 
-        Ok(Self {
+        // pricing strategies per market and fare multipliers per vehicle class, e.g.
+        // PRICING_CONFIG='{"default":{"type":"median_driver_fare"},"class_multipliers":{"xl":1.5}}'
+        let pricing: PricingConfig = env_json("PRICING_CONFIG")?.unwrap_or_default();
+        pricing.ensure_valid()?;
+
+        // e.g. METERING_CONFIG='{"enabled":true,"per_minute":0.25,"tolerance":0.2}'
+        let metering = env_json("METERING_CONFIG")?.unwrap_or_default();
+
+        // e.g. SURGE_CONFIG='{"precision":6,"max_multiplier":3.0,"sensitivity":0.5,"smoothing":0.5}'
+        let surge: SurgeConfig = env_json("SURGE_CONFIG")?.unwrap_or_default();
+        surge.ensure_valid()?;
+
+        // e.g. SAFETY_CONFIG='{"max_deviation":500.0,"max_stop":300,"stop_radius":30.0,"overdue_factor":2.0,"average_speed":8.0}'
+        let safety = env_json("SAFETY_CONFIG")?.unwrap_or_default();
+
+        // e.g. SCHEDULING_CONFIG='{"lead_time":900,"min_advance":1800,"max_advance":604800}'
+        let scheduling = env_json("SCHEDULING_CONFIG")?.unwrap_or_default();
+
+        // e.g. POOLING_CONFIG='{"max_passengers":3,"max_detour":0.5,"discount":0.25,"search_radius":3000.0}'
+        let pooling = env_json("POOLING_CONFIG")?.unwrap_or_default();
+
+        // e.g. DISPATCH_CONFIG='{"mode":"broadcast","size":3}' or
+        // DISPATCH_CONFIG='{"mode":"batch","interval":5,"precision":5,"search_radius":2000.0}'
+        let dispatch = env_json("DISPATCH_CONFIG")?.unwrap_or_default();
+
+        // e.g. ETA_CONFIG='{"enabled":true,"max_candidates":20,"cache_precision":7,"cache_ttl":120,"average_speed":8.0,"detour_factor":1.3}'
+        let eta = env_json("ETA_CONFIG")?.unwrap_or_default();
+
+        // e.g. SCORING_CONFIG='{"acceptance_weight":1.0,"cancellation_weight":1.0,"punctuality_weight":0.5,"rating_weight":1.0,"idle_weight":0.5,"distance_weight":1.0}'
+        let scoring = env_json("SCORING_CONFIG")?.unwrap_or_default();
+        let quote_ttl = env_parse("QUOTE_TTL")?.unwrap_or(300);
+        let stats_window = env_parse("DRIVER_STATS_WINDOW")?.unwrap_or(30);
+        let stale_location_grace = env_parse("STALE_LOCATION_GRACE")?.unwrap_or(300);
+
+        // e.g. SPOOFING_CONFIG='{"max_speed":70.0,"max_repeats":30,"withhold_flagged":true,"withhold_duration":900}'
+        let spoofing = env_json("SPOOFING_CONFIG")?.unwrap_or_default();
+
+        Ok(Arc::new_cyclic(|this| Self {
+            this: this.clone(),
             pool,
             authorizor: authorizor::new(),
-        })
+            fare_adjustments,
+            pricing,
+            metering,
+            surge,
+            safety,
+            scheduling,
+            pooling,
+            dispatch,
+            eta,
+            scoring,
+            quote_ttl,
+            stats_window,
+            stale_location_grace,
+            spoofing,
+            events: broadcast::channel(1024).0,
+            locations: broadcast::channel(1024).0,
+            pending_locations: Mutex::default(),
+            pending_trace: Mutex::default(),
+            eta_cache: Mutex::default(),
+            location_tracks: Mutex::default(),
+        }))
+    }
+}
+
+fn env_parse<T: FromStr>(key: &str) -> Result<Option<T>, Error> {
+    match env::var(key) {
+        Ok(value) => {
+            let value = value.parse::<T>().map_err(|_| invalid_input_error())?;
+            Ok(Some(value))
+        }
+        Err(env::VarError::NotPresent) => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+fn env_json<T: DeserializeOwned>(key: &str) -> Result<Option<T>, Error> {
+    match env::var(key) {
+        Ok(value) => {
+            let value = serde_json::from_str(&value).map_err(|_| invalid_input_error())?;
+            Ok(Some(value))
+        }
+        Err(env::VarError::NotPresent) => Ok(None),
+        Err(err) => Err(err.into()),
     }
 }
 
 impl Engine {
+    /// Returns a shared handle to the engine, for tasks that outlive the call spawning them.
+    fn shared(&self) -> Arc<Engine> {
+        self.this.upgrade().expect("engine is dropped")
+    }
+
     pub fn authorize<Actor, Action, Resource>(
         &self,
         actor: Actor,
@@ -106,6 +298,37 @@ impl Engine {
 
         Err(unauthorized_error())
     }
+
+    /// Returns the fare of a route at the rate of a driver, or the tariff in markets priced with
+    /// one, before any adjustments.
+    fn route_fare(
+        &self,
+        route: &Route,
+        min_fare: f64,
+        rate: f64,
+        pickup_distance: f64,
+    ) -> FareBreakdown {
+        match self.pricing.tariff(route) {
+            Some(tariff) => tariff.fare(route.distance),
+            None => FareBreakdown::from_rate(min_fare, rate, pickup_distance, route.distance),
+        }
+    }
+
+    /// Scales a fare by the multiplier of the vehicle class and applies the platform adjustments,
+    /// along with the pooling discount for pooled trips.
+    fn adjust_fare(
+        &self,
+        breakdown: FareBreakdown,
+        vehicle_class: VehicleClass,
+        is_pooled: bool,
+    ) -> FareBreakdown {
+        let breakdown = breakdown.scaled(self.pricing.class_multiplier(vehicle_class));
+
+        match is_pooled {
+            true => self.pooling.price(breakdown, &self.fare_adjustments),
+            false => breakdown.with_adjustments(&self.fare_adjustments),
+        }
+    }
 }
 
 impl API for Engine {}
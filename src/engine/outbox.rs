@@ -0,0 +1,77 @@
+use super::Engine;
+
+use chrono::{DateTime, Utc};
+use sqlx::{types::Json, Acquire, Executor, Row};
+use tokio::sync::broadcast;
+
+use crate::{
+    entities::{DomainEvent, OutboxEvent},
+    error::Error,
+};
+
+// postgres channel that published events are sent to via NOTIFY
+pub const NOTIFY_CHANNEL: &str = "domain_events";
+
+impl Engine {
+    /// Subscribes to domain events as they are published by the outbox relay.
+    pub fn subscribe(&self) -> broadcast::Receiver<OutboxEvent> {
+        self.events.subscribe()
+    }
+
+    /// Publishes unpublished outbox events, in the order they were written, to in-process
+    /// subscribers and to postgres LISTEN/NOTIFY, marking them as published.
+    #[tracing::instrument(skip(self))]
+    pub async fn relay_events(&self) -> Result<(), Error> {
+        let mut conn = self.pool.acquire().await?;
+
+        loop {
+            let mut tx = conn.begin().await?;
+
+            let results = tx
+                .fetch_all(sqlx::query(
+                    "SELECT id, created_at, event FROM outbox WHERE published_at IS NULL ORDER BY id ASC LIMIT 100 FOR UPDATE SKIP LOCKED",
+                ))
+                .await?;
+
+            if results.is_empty() {
+                return Ok(());
+            }
+
+            let mut events = vec![];
+
+            for result in results.iter() {
+                let created_at: DateTime<Utc> = result.try_get("created_at")?;
+                let Json(event): Json<DomainEvent> = result.try_get("event")?;
+
+                events.push(OutboxEvent {
+                    id: result.try_get("id")?,
+                    created_at,
+                    event,
+                });
+            }
+
+            for event in events.iter() {
+                tx.execute(
+                    sqlx::query("SELECT pg_notify($1, $2::TEXT)")
+                        .bind(NOTIFY_CHANNEL)
+                        .bind(Json(event)),
+                )
+                .await?;
+
+                tx.execute(
+                    sqlx::query("UPDATE outbox SET published_at = now() WHERE id = $1")
+                        .bind(event.id),
+                )
+                .await?;
+            }
+
+            tx.commit().await?;
+
+            // notifications are delivered on commit, in-process subscribers are notified after
+            for event in events.into_iter() {
+                // sending only fails when there are no subscribers
+                let _ = self.events.send(event);
+            }
+        }
+    }
+}
@@ -0,0 +1,283 @@
+use serde::{Deserialize, Serialize};
+
+use crate::entities::{Coordinates, FareAdjustments, FareBreakdown, PoolStop, PoolStopKind};
+
+/// Settings of pooled trips, with distances in meters.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PoolingConfig {
+    // how many pooled trips a driver may serve at once
+    pub max_passengers: usize,
+    // how much longer than the direct distance each passenger's ride may become, as a fraction
+    pub max_detour: f64,
+    // the share of the fare pooled passengers are discounted
+    pub discount: f64,
+    // how far a driver may travel along their stops to pick up a passenger joining the pool
+    pub search_radius: f64,
+}
+
+impl Default for PoolingConfig {
+    fn default() -> Self {
+        Self {
+            max_passengers: 3,
+            max_detour: 0.5,
+            discount: 0.25,
+            search_radius: 3000.0,
+        }
+    }
+}
+
+impl PoolingConfig {
+    /// Applies the platform adjustments to the fare of a pooled trip along with the pooling
+    /// discount, which is taken off the fare before fees and taxes.
+    pub fn price(&self, breakdown: FareBreakdown, adjustments: &FareAdjustments) -> FareBreakdown {
+        let discount = breakdown.total * self.discount;
+
+        breakdown.with_adjustments(&FareAdjustments {
+            discount: adjustments.discount + discount,
+            ..adjustments.clone()
+        })
+    }
+
+    /// Returns the most a driver may charge before the pooling discount for the discounted fare
+    /// to stay within the max fare of a pooled trip.
+    pub fn fare_limit(&self, max_fare: f64) -> f64 {
+        max_fare / (1.0 - self.discount).max(0.0)
+    }
+
+    /// Finds where to insert the pickup and dropoff of a new trip into the stops a driver has
+    /// yet to visit from their current position, such that the detour limit holds for every
+    /// passenger and the new passenger is picked up within the search radius. Pools hold no more
+    /// passengers than the vehicle has seats for. Returns the stops
+    /// with the insertion that adds the least distance along with the distance added, or `None`
+    /// if the trip cannot join the pool.
+    pub fn insert(
+        &self,
+        position: &Coordinates,
+        stops: &[PoolStop],
+        pickup: PoolStop,
+        dropoff: PoolStop,
+        capacity: usize,
+    ) -> Option<(Vec<PoolStop>, f64)> {
+        // every trip in the pool has its dropoff ahead
+        let passengers = stops
+            .iter()
+            .filter(|stop| stop.kind == PoolStopKind::Dropoff)
+            .count();
+
+        if passengers >= self.max_passengers.min(capacity) {
+            return None;
+        }
+
+        let length = path_length(position, stops);
+        let mut best: Option<(Vec<PoolStop>, f64)> = None;
+
+        for i in 0..=stops.len() {
+            for j in i..=stops.len() {
+                let mut candidate = stops.to_vec();
+                candidate.insert(j, dropoff.clone());
+                candidate.insert(i, pickup.clone());
+
+                if path_length(position, &candidate[..=i]) > self.search_radius
+                    || !self.within_detour(position, &candidate)
+                {
+                    continue;
+                }
+
+                let added = path_length(position, &candidate) - length;
+
+                if best.as_ref().is_none_or(|(_, distance)| added < *distance) {
+                    best = Some((candidate, added));
+                }
+            }
+        }
+
+        best
+    }
+
+    /// Checks that no passenger rides more than the detour limit beyond the direct distance
+    /// between their pickup, or the current position for passengers aboard, and their dropoff.
+    fn within_detour(&self, position: &Coordinates, stops: &[PoolStop]) -> bool {
+        let mut travelled = vec![];
+        let mut total = 0.0;
+        let mut last = position;
+
+        for stop in stops.iter() {
+            total += last.distance(&stop.coordinates);
+            travelled.push(total);
+            last = &stop.coordinates;
+        }
+
+        stops
+            .iter()
+            .enumerate()
+            .filter(|(_, stop)| stop.kind == PoolStopKind::Dropoff)
+            .all(|(j, dropoff)| {
+                let pickup = stops[..j].iter().position(|stop| {
+                    stop.trip_id == dropoff.trip_id && stop.kind == PoolStopKind::Pickup
+                });
+
+                let (start, direct) = match pickup {
+                    Some(i) => (
+                        travelled[i],
+                        stops[i].coordinates.distance(&dropoff.coordinates),
+                    ),
+                    None => (0.0, position.distance(&dropoff.coordinates)),
+                };
+
+                travelled[j] - start <= direct * (1.0 + self.max_detour)
+            })
+    }
+}
+
+/// Returns the distance from the position through the stops in order, in straight lines.
+fn path_length(position: &Coordinates, stops: &[PoolStop]) -> f64 {
+    let mut total = 0.0;
+    let mut last = position;
+
+    for stop in stops.iter() {
+        total += last.distance(&stop.coordinates);
+        last = &stop.coordinates;
+    }
+
+    total
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use uuid::Uuid;
+
+    use crate::entities::LineItemKind;
+
+    fn stop(trip_id: Uuid, kind: PoolStopKind, lng: f64) -> PoolStop {
+        PoolStop {
+            trip_id,
+            kind,
+            coordinates: Coordinates { lat: 0.0, lng },
+        }
+    }
+
+    #[test]
+    fn insert_test() {
+        let config = PoolingConfig::default();
+        let position = Coordinates { lat: 0.0, lng: 0.0 };
+
+        let a = Uuid::new_v4();
+        let b = Uuid::new_v4();
+
+        let stops = vec![
+            stop(a, PoolStopKind::Pickup, 0.01),
+            stop(a, PoolStopKind::Dropoff, 0.05),
+        ];
+
+        // a trip along the way fits in between without adding any distance
+        let (inserted, added) = config
+            .insert(
+                &position,
+                &stops,
+                stop(b, PoolStopKind::Pickup, 0.02),
+                stop(b, PoolStopKind::Dropoff, 0.04),
+                4,
+            )
+            .unwrap();
+
+        assert!(added.abs() < 1.0);
+        assert_eq!(
+            inserted
+                .iter()
+                .map(|stop| stop.coordinates.lng)
+                .collect::<Vec<_>>(),
+            vec![0.01, 0.02, 0.04, 0.05]
+        );
+
+        // a trip in the opposite direction would either detour the passenger aboard too far or
+        // wait for them to be dropped off first
+        assert!(config
+            .insert(
+                &position,
+                &stops[1..],
+                stop(b, PoolStopKind::Pickup, 0.02),
+                stop(b, PoolStopKind::Dropoff, -0.03),
+                4,
+            )
+            .is_none());
+
+        // the vehicle has no seat left
+        assert!(config
+            .insert(
+                &position,
+                &stops,
+                stop(b, PoolStopKind::Pickup, 0.02),
+                stop(b, PoolStopKind::Dropoff, 0.04),
+                1,
+            )
+            .is_none());
+
+        // the pool is full
+        let config = PoolingConfig {
+            max_passengers: 1,
+            ..config
+        };
+
+        assert!(config
+            .insert(
+                &position,
+                &stops,
+                stop(b, PoolStopKind::Pickup, 0.02),
+                stop(b, PoolStopKind::Dropoff, 0.04),
+                4,
+            )
+            .is_none());
+    }
+
+    #[test]
+    fn price_test() {
+        let config = PoolingConfig::default();
+        let adjustments = FareAdjustments {
+            booking_fee: 1.0,
+            tax_rate: 0.0,
+            discount: 0.0,
+        };
+
+        let breakdown = config.price(FareBreakdown::fixed(20.0), &adjustments);
+
+        assert_eq!(breakdown.amount(LineItemKind::Discount), -5.0);
+        assert_eq!(breakdown.total, 16.0);
+    }
+
+    #[test]
+    fn fare_limit_test() {
+        use crate::engine::pricing::{DriverRate, MedianDriverFare, PricingStrategy};
+        use crate::entities::{Location, Route};
+
+        let config = PoolingConfig::default();
+        let adjustments = FareAdjustments {
+            booking_fee: 0.0,
+            tax_rate: 0.0,
+            discount: 0.0,
+        };
+
+        let origin = Location::new(Coordinates { lat: 0.0, lng: 0.0 }, "".into());
+        let route = Route::new(origin.clone(), origin, serde_json::json!({}), 1000.0);
+
+        let drivers: Vec<DriverRate> = [0.01, 0.02, 0.03]
+            .into_iter()
+            .map(|rate| DriverRate {
+                min_fare: 0.0,
+                rate,
+                pickup_distance: 0.0,
+            })
+            .collect();
+
+        let breakdown = MedianDriverFare.price(&route, &drivers).unwrap();
+        let max_fare = config.price(breakdown, &adjustments).total;
+
+        // the median driver charges more than the discounted max fare of a pooled trip, but
+        // remains a candidate as their fare is discounted as well
+        let median_fare = 0.02 * route.distance;
+        assert!(median_fare > max_fare);
+        assert!(median_fare <= config.fare_limit(max_fare));
+        assert!(0.03 * route.distance > config.fare_limit(max_fare));
+    }
+}
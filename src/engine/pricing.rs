@@ -0,0 +1,430 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::entities::{Coordinates, FareBreakdown, LineItem, LineItemKind, Route, VehicleClass};
+use crate::error::{invalid_input_error, Error};
+
+/// The rate of a driver that is available to serve a route.
+#[derive(Clone, Debug)]
+pub struct DriverRate {
+    pub min_fare: f64,
+    pub rate: f64,
+    pub pickup_distance: f64,
+}
+
+pub trait PricingStrategy {
+    /// Returns the max fare of the route given the rates of nearby available drivers, or `None`
+    /// if no price can be offered.
+    fn price(&self, route: &Route, drivers: &[DriverRate]) -> Option<FareBreakdown>;
+}
+
+/// Prices a route at the median of the fares of nearby drivers.
+pub struct MedianDriverFare;
+
+impl PricingStrategy for MedianDriverFare {
+    fn price(&self, route: &Route, drivers: &[DriverRate]) -> Option<FareBreakdown> {
+        percentile(driver_fares(route, drivers), 0.5)
+    }
+}
+
+/// Prices a route at a percentile of the fares of nearby drivers, so that roughly that share of
+/// drivers can serve the trip within its max fare.
+pub struct MaxOfPercentile {
+    pub percentile: f64,
+}
+
+impl PricingStrategy for MaxOfPercentile {
+    fn price(&self, route: &Route, drivers: &[DriverRate]) -> Option<FareBreakdown> {
+        percentile(driver_fares(route, drivers), self.percentile)
+    }
+}
+
+/// Prices a route with a platform-set tariff, regardless of the rates of nearby drivers. Trips in
+/// markets priced with a tariff are charged the tariff rather than the rates of their drivers. The
+/// duration of the route is estimated from its distance and the average speed, in meters per
+/// second.
+#[derive(Clone, Debug)]
+pub struct Tariff {
+    pub base: f64,
+    pub per_km: f64,
+    pub per_minute: f64,
+    pub average_speed: f64,
+}
+
+impl PricingStrategy for Tariff {
+    fn price(&self, route: &Route, drivers: &[DriverRate]) -> Option<FareBreakdown> {
+        if drivers.is_empty() {
+            return None;
+        }
+
+        Some(self.fare(route.distance))
+    }
+}
+
+impl Tariff {
+    /// Returns the tariff of a distance, in meters, at the average speed.
+    pub fn fare(&self, distance: f64) -> FareBreakdown {
+        self.metered(distance, distance / self.average_speed / 60.0)
+    }
+
+    /// Returns the tariff of a distance, in meters, driven in the given number of minutes.
+    pub fn metered(&self, distance: f64, minutes: f64) -> FareBreakdown {
+        FareBreakdown::from_items(vec![
+            LineItem::new(LineItemKind::Base, self.base),
+            LineItem::new(LineItemKind::Distance, self.per_km * distance / 1000.0),
+            LineItem::new(LineItemKind::Time, self.per_minute * minutes),
+        ])
+    }
+}
+
+fn driver_fares(route: &Route, drivers: &[DriverRate]) -> Vec<FareBreakdown> {
+    drivers
+        .iter()
+        .map(|driver| {
+            FareBreakdown::from_rate(
+                driver.min_fare,
+                driver.rate,
+                driver.pickup_distance,
+                route.distance,
+            )
+        })
+        .collect()
+}
+
+/// Returns the given percentile of the fares, interpolating linearly between the two closest
+/// fares (equivalent to `percentile_cont`).
+pub fn percentile(mut fares: Vec<FareBreakdown>, percentile: f64) -> Option<FareBreakdown> {
+    if fares.is_empty() {
+        return None;
+    }
+
+    fares.sort_by(|a, b| a.total.total_cmp(&b.total));
+
+    let position = percentile.clamp(0.0, 1.0) * (fares.len() - 1) as f64;
+    let lower = position.floor() as usize;
+    let upper = position.ceil() as usize;
+
+    Some(FareBreakdown::interpolate(
+        &fares[lower],
+        &fares[upper],
+        position - lower as f64,
+    ))
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PricingStrategyConfig {
+    MedianDriverFare,
+    MaxOfPercentile {
+        percentile: f64,
+    },
+    Tariff {
+        base: f64,
+        per_km: f64,
+        per_minute: f64,
+        average_speed: f64,
+    },
+}
+
+impl PricingStrategyConfig {
+    pub fn build(&self) -> Box<dyn PricingStrategy + Send + Sync> {
+        if let Some(tariff) = self.tariff() {
+            return Box::new(tariff);
+        }
+
+        match self {
+            Self::MaxOfPercentile { percentile } => Box::new(MaxOfPercentile {
+                percentile: *percentile,
+            }),
+            _ => Box::new(MedianDriverFare),
+        }
+    }
+
+    pub fn tariff(&self) -> Option<Tariff> {
+        match self {
+            Self::Tariff {
+                base,
+                per_km,
+                per_minute,
+                average_speed,
+            } => Some(Tariff {
+                base: *base,
+                per_km: *per_km,
+                per_minute: *per_minute,
+                average_speed: *average_speed,
+            }),
+            _ => None,
+        }
+    }
+
+    fn is_valid(&self) -> bool {
+        match self {
+            Self::MedianDriverFare => true,
+            Self::MaxOfPercentile { percentile } => (0.0..=1.0).contains(percentile),
+            Self::Tariff { average_speed, .. } => *average_speed > 0.0,
+        }
+    }
+}
+
+/// A geographic area with its own pricing strategy, bounded by a lat/lng box.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Market {
+    pub name: String,
+    pub min: Coordinates,
+    pub max: Coordinates,
+    pub strategy: PricingStrategyConfig,
+}
+
+impl Market {
+    pub fn contains(&self, coordinates: &Coordinates) -> bool {
+        coordinates.lat >= self.min.lat
+            && coordinates.lat <= self.max.lat
+            && coordinates.lng >= self.min.lng
+            && coordinates.lng <= self.max.lng
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PricingConfig {
+    pub default: PricingStrategyConfig,
+    #[serde(default)]
+    pub markets: Vec<Market>,
+    // fares of trips in each vehicle class are scaled by its multiplier, which defaults to one
+    #[serde(default)]
+    pub class_multipliers: HashMap<VehicleClass, f64>,
+}
+
+impl Default for PricingConfig {
+    fn default() -> Self {
+        Self {
+            default: PricingStrategyConfig::MedianDriverFare,
+            markets: vec![],
+            class_multipliers: HashMap::new(),
+        }
+    }
+}
+
+impl PricingConfig {
+    pub fn ensure_valid(&self) -> Result<(), Error> {
+        let markets_valid = self.markets.iter().all(|market| {
+            market.min.lat <= market.max.lat
+                && market.min.lng <= market.max.lng
+                && market.strategy.is_valid()
+        });
+
+        if !(self.default.is_valid() && markets_valid) {
+            return Err(invalid_input_error());
+        }
+
+        Ok(())
+    }
+
+    /// Returns the strategy of the first market containing the origin of the route, falling back
+    /// to the default strategy.
+    pub fn strategy(&self, route: &Route) -> Box<dyn PricingStrategy + Send + Sync> {
+        self.strategy_config(route).build()
+    }
+
+    /// Returns the tariff trips along the route are charged, if their market is priced with one.
+    pub fn tariff(&self, route: &Route) -> Option<Tariff> {
+        self.strategy_config(route).tariff()
+    }
+
+    fn strategy_config(&self, route: &Route) -> &PricingStrategyConfig {
+        self.markets
+            .iter()
+            .find(|market| market.contains(&route.origin.coordinates))
+            .map(|market| &market.strategy)
+            .unwrap_or(&self.default)
+    }
+
+    pub fn class_multiplier(&self, class: VehicleClass) -> f64 {
+        self.class_multipliers.get(&class).copied().unwrap_or(1.0)
+    }
+}
+
+/// Settings of metered fares, where the final fare of a trip is computed from its trace at dropoff
+/// and kept within `tolerance` (a fraction) of the upfront fare.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MeteringConfig {
+    pub enabled: bool,
+    pub per_minute: f64,
+    pub tolerance: f64,
+}
+
+impl Default for MeteringConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            per_minute: 0.0,
+            tolerance: 0.2,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::entities::Location;
+
+    fn new_route(lat: f64, lng: f64, distance: f64) -> Route {
+        let origin = Location::new(Coordinates { lat, lng }, "".into());
+        let destination = origin.clone();
+        Route::new(origin, destination, serde_json::json!({}), distance)
+    }
+
+    fn new_driver(min_fare: f64, rate: f64) -> DriverRate {
+        DriverRate {
+            min_fare,
+            rate,
+            pickup_distance: 0.0,
+        }
+    }
+
+    #[test]
+    fn median_driver_fare_test() {
+        let route = new_route(0.0, 0.0, 1000.0);
+        let strategy = MedianDriverFare;
+
+        assert!(strategy.price(&route, &[]).is_none());
+
+        let drivers = vec![
+            new_driver(0.0, 0.03),
+            new_driver(0.0, 0.01),
+            new_driver(0.0, 0.02),
+        ];
+        let breakdown = strategy.price(&route, &drivers).unwrap();
+        assert_eq!(breakdown.total, 20.0);
+
+        let drivers = vec![new_driver(0.0, 0.01), new_driver(0.0, 0.02)];
+        let breakdown = strategy.price(&route, &drivers).unwrap();
+        assert_eq!(breakdown.total, 15.0);
+    }
+
+    #[test]
+    fn max_of_percentile_test() {
+        let route = new_route(0.0, 0.0, 1000.0);
+        let strategy = MaxOfPercentile { percentile: 0.75 };
+
+        let drivers = vec![
+            new_driver(0.0, 0.01),
+            new_driver(0.0, 0.02),
+            new_driver(0.0, 0.03),
+            new_driver(0.0, 0.04),
+            new_driver(0.0, 0.05),
+        ];
+        let breakdown = strategy.price(&route, &drivers).unwrap();
+        assert_eq!(breakdown.total, 40.0);
+
+        let strategy = MaxOfPercentile { percentile: 1.0 };
+        let breakdown = strategy.price(&route, &drivers).unwrap();
+        assert_eq!(breakdown.total, 50.0);
+    }
+
+    #[test]
+    fn tariff_test() {
+        let route = new_route(0.0, 0.0, 6000.0);
+        let strategy = Tariff {
+            base: 2.0,
+            per_km: 1.5,
+            per_minute: 0.5,
+            average_speed: 10.0,
+        };
+
+        assert!(strategy.price(&route, &[]).is_none());
+
+        let breakdown = strategy.price(&route, &[new_driver(0.0, 0.01)]).unwrap();
+        assert_eq!(breakdown.amount(LineItemKind::Base), 2.0);
+        assert_eq!(breakdown.amount(LineItemKind::Distance), 9.0);
+        assert_eq!(breakdown.amount(LineItemKind::Time), 5.0);
+        assert_eq!(breakdown.total, 16.0);
+
+        // metered trips are charged the minutes actually driven
+        let breakdown = strategy.metered(6000.0, 20.0);
+        assert_eq!(breakdown.amount(LineItemKind::Time), 10.0);
+        assert_eq!(breakdown.total, 21.0);
+    }
+
+    #[test]
+    fn market_selection_test() {
+        let config: PricingConfig = serde_json::from_value(serde_json::json!({
+            "default": { "type": "median_driver_fare" },
+            "markets": [{
+                "name": "male",
+                "min": { "lat": 4.0, "lng": 73.0 },
+                "max": { "lat": 5.0, "lng": 74.0 },
+                "strategy": {
+                    "type": "tariff",
+                    "base": 10.0,
+                    "per_km": 0.0,
+                    "per_minute": 0.0,
+                    "average_speed": 10.0
+                }
+            }]
+        }))
+        .unwrap();
+
+        let drivers = vec![new_driver(0.0, 0.01)];
+
+        let route = new_route(4.17, 73.51, 1000.0);
+        let breakdown = config.strategy(&route).price(&route, &drivers).unwrap();
+        assert_eq!(breakdown.total, 10.0);
+
+        let route = new_route(0.0, 0.0, 1000.0);
+        let breakdown = config.strategy(&route).price(&route, &drivers).unwrap();
+        assert_eq!(breakdown.total, 10.0);
+        assert_eq!(breakdown.amount(LineItemKind::Distance), 10.0);
+
+        assert!(config.tariff(&new_route(4.17, 73.51, 1000.0)).is_some());
+        assert!(config.tariff(&route).is_none());
+    }
+
+    #[test]
+    fn invalid_config_test() {
+        assert!(PricingConfig::default().ensure_valid().is_ok());
+
+        let configs = [
+            serde_json::json!({
+                "default": { "type": "max_of_percentile", "percentile": 1.5 }
+            }),
+            serde_json::json!({
+                "default": {
+                    "type": "tariff",
+                    "base": 2.0,
+                    "per_km": 1.5,
+                    "per_minute": 0.5,
+                    "average_speed": 0.0
+                }
+            }),
+            serde_json::json!({
+                "default": { "type": "median_driver_fare" },
+                "markets": [{
+                    "name": "male",
+                    "min": { "lat": 5.0, "lng": 73.0 },
+                    "max": { "lat": 4.0, "lng": 74.0 },
+                    "strategy": { "type": "median_driver_fare" }
+                }]
+            }),
+        ];
+
+        for config in configs {
+            let config: PricingConfig = serde_json::from_value(config).unwrap();
+            assert!(config.ensure_valid().is_err());
+        }
+    }
+
+    #[test]
+    fn class_multiplier_test() {
+        let config: PricingConfig = serde_json::from_value(serde_json::json!({
+            "default": { "type": "median_driver_fare" },
+            "class_multipliers": { "comfort": 1.4, "xl": 1.8 }
+        }))
+        .unwrap();
+
+        assert_eq!(config.class_multiplier(VehicleClass::Economy), 1.0);
+        assert_eq!(config.class_multiplier(VehicleClass::Comfort), 1.4);
+        assert_eq!(config.class_multiplier(VehicleClass::Xl), 1.8);
+    }
+}
@@ -1,81 +1,161 @@
-use super::Engine;
+use super::pricing::{percentile, DriverRate};
+use super::{Database, Engine};
 
 use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
 use geo_types::Geometry;
 use geozero::wkb;
-use sqlx::{types::Json, Executor, Row};
+use sqlx::{pool::PoolConnection, types::Json, Executor, Row};
 use uuid::Uuid;
 
 use crate::{
     api::{QuoteAPI, RouteAPI},
     auth::User,
-    entities::Quote,
+    entities::{Coordinates, FareBreakdown, Quote, Route, VehicleClass},
     error::{invalid_input_error, Error},
 };
 
 #[async_trait]
 impl QuoteAPI for Engine {
     #[tracing::instrument(skip(self))]
-    async fn create_quote(&self, user: User, route_token: Uuid) -> Result<Option<Quote>, Error> {
+    async fn create_quote(
+        &self,
+        user: User,
+        route_token: Uuid,
+        pickup_at: Option<DateTime<Utc>>,
+        is_pooled: bool,
+        vehicle_class: VehicleClass,
+    ) -> Result<Option<Quote>, Error> {
+        if let Some(pickup_at) = pickup_at {
+            self.scheduling.ensure_schedulable(pickup_at)?;
+        }
+
         let route = self.find_route(user.clone(), route_token).await?;
 
+        // pools are matched on demand and only combine direct routes
+        if is_pooled && (pickup_at.is_some() || !route.stops.is_empty()) {
+            return Err(invalid_input_error());
+        }
+
         let origin_location: Geometry<f64> = route.origin.coordinates.clone().into();
         let search_radius = 2000.0;
 
+        // locations are stored as (lat, lng) points, so they are flipped to measure distances in
+        // meters. drivers are prefiltered by straight-line distance before their pickup legs are
+        // routed. drivers without a registered vehicle are taken to drive an economy vehicle
         let query = "
             SELECT
-                percentile_cont(0.5) WITHIN GROUP (
-                    ORDER BY
-                        fares.fare ASC
-                ) AS max_fare
+                r.min_fare::FLOAT8 AS min_fare,
+                r.rate::FLOAT8 AS rate,
+                ST_X(l.location) AS lat,
+                ST_Y(l.location) AS lng
             FROM
-                (
-                    SELECT
-                        GREATEST(
-                            r.min_fare, r.rate * (
-                                ST_Distance(l.location, ST_SetSRID($1, 4326)) + $2
-                            )
-                        ) AS fare
-                    FROM
-                        drivers d
-                        LEFT JOIN driver_rates r ON d.id = r.driver_id
-                        LEFT JOIN driver_locations l ON d.id = l.driver_id
-                    WHERE
-                        d.status = 'available'
-                        AND r.rate IS NOT NULL
-                        AND l.location IS NOT NULL
-                        AND l.expiry > now()
-                        AND ST_DWithin(l.location, ST_SetSRID($1, 4326), $3)
-                ) AS fares
+                drivers d
+                LEFT JOIN driver_rates r ON d.id = r.driver_id
+                LEFT JOIN driver_locations l ON d.id = l.driver_id
+                LEFT JOIN vehicles v ON d.id = v.driver_id
+            WHERE
+                d.status = 'available'
+                AND COALESCE(v.class, 'economy') = $4
+                AND r.rate IS NOT NULL
+                AND l.location IS NOT NULL
+                AND l.expiry > now()
+                AND ST_DWithin(
+                    ST_FlipCoordinates(l.location)::geography,
+                    ST_FlipCoordinates(ST_SetSRID($1, 4326))::geography,
+                    $2
+                )
+            ORDER BY
+                ST_Distance(
+                    ST_FlipCoordinates(l.location)::geography,
+                    ST_FlipCoordinates(ST_SetSRID($1, 4326))::geography
+                ) ASC
+            LIMIT $3
         ";
 
         let mut conn = self.pool.acquire().await?;
 
-        let maybe_max_fare: Option<f64> = conn
-            .fetch_one(
+        let results = conn
+            .fetch_all(
                 sqlx::query(query)
                     .bind(wkb::Encode(origin_location))
-                    .bind(route.distance)
-                    .bind(search_radius),
+                    .bind(search_radius)
+                    .bind(self.eta.max_candidates)
+                    .bind(vehicle_class.name()),
             )
-            .await?
-            .try_get("max_fare")?;
+            .await?;
 
-        match maybe_max_fare {
-            Some(max_fare) => {
-                let quote = Quote::new(route, max_fare);
+        let mut rates = vec![];
+        let mut positions = vec![];
 
-                conn.execute(
-                    sqlx::query("INSERT INTO quotes (token, data) VALUES ($1, $2)")
-                        .bind(&quote.token)
-                        .bind(Json(&quote)),
-                )
-                .await?;
+        for result in results.iter() {
+            let min_fare: Option<f64> = result.try_get("min_fare")?;
+            let rate: f64 = result.try_get("rate")?;
+
+            rates.push((min_fare.unwrap_or(0.0), rate));
+            positions.push(Coordinates {
+                lat: result.try_get("lat")?,
+                lng: result.try_get("lng")?,
+            });
+        }
+
+        let legs = self
+            .pickup_legs(&positions, &route.origin.coordinates)
+            .await;
+
+        let drivers: Vec<DriverRate> = rates
+            .into_iter()
+            .zip(legs)
+            .map(|((min_fare, rate), leg)| DriverRate {
+                min_fare,
+                rate,
+                pickup_distance: leg.distance,
+            })
+            .collect();
+
+        let quote_ttl = Duration::seconds(self.quote_ttl);
 
-                Ok(Some(quote))
+        let maybe_quote = match self.pricing.strategy(&route).price(&route, &drivers) {
+            Some(breakdown) => {
+                // current demand says little about the demand at a pickup time in the future
+                let surge_multiplier = match pickup_at {
+                    Some(_) => 1.0,
+                    None => self.surge_multiplier(&route.origin.coordinates).await?,
+                };
+
+                let breakdown = self.adjust_fare(
+                    breakdown.with_surge(surge_multiplier),
+                    vehicle_class,
+                    is_pooled,
+                );
+                Some(Quote::new(user.id, route, breakdown, quote_ttl))
+            }
+            None => {
+                tracing::info!("no drivers nearby, estimating fare from completed trips...");
+
+                estimate_fare(&mut conn, &route, search_radius, vehicle_class)
+                    .await?
+                    .map(|breakdown| Quote::new_estimate(user.id, route, breakdown, quote_ttl))
             }
-            None => Ok(None),
+        };
+
+        let maybe_quote = maybe_quote.map(|quote| Quote {
+            pickup_at,
+            is_pooled,
+            vehicle_class,
+            ..quote
+        });
+
+        if let Some(quote) = &maybe_quote {
+            conn.execute(
+                sqlx::query("INSERT INTO quotes (token, data) VALUES ($1, $2)")
+                    .bind(&quote.token)
+                    .bind(Json(quote)),
+            )
+            .await?;
         }
+
+        Ok(maybe_quote)
     }
 
     #[tracing::instrument(skip(self))]
@@ -89,8 +169,72 @@ impl QuoteAPI for Engine {
             .await?;
 
         let result = maybe_result.ok_or_else(|| invalid_input_error())?;
-        let Json(quote) = result.try_get("data")?;
+        let Json(quote): Json<Quote> = result.try_get("data")?;
+
+        self.authorize(user, "read", quote.clone())?;
+
+        quote.ensure_unexpired()?;
 
         Ok(quote)
     }
 }
+
+/// Estimates the fare of a route from the median fare of completed trips of a similar distance
+/// of the same vehicle class that started within the search radius of its origin, preferring
+/// metered fares. Pooled trips are left out as their fares are discounted.
+async fn estimate_fare(
+    conn: &mut PoolConnection<Database>,
+    route: &Route,
+    search_radius: f64,
+    vehicle_class: VehicleClass,
+) -> Result<Option<FareBreakdown>, Error> {
+    let origin_location: Geometry<f64> = route.origin.coordinates.clone().into();
+    let distance_tolerance = 0.25;
+
+    // the origin of the route is a (lat, lng) point, so it is flipped to measure distances in
+    // meters against the (lng, lat) origins of past trips
+    let query = "
+        SELECT
+            COALESCE(data->'metered_fare'->'breakdown', data->'fare_breakdown') AS fare_breakdown
+        FROM
+            trips
+        WHERE
+            status = 'completed'
+            AND data->'fare_breakdown' IS NOT NULL
+            AND NOT (data->>'is_pooled')::BOOLEAN
+            AND data->>'vehicle_class' = $5
+            AND ABS((data->'route'->>'distance')::FLOAT8 - $2) <= $2 * $4
+            AND ST_DWithin(
+                ST_SetSRID(
+                    ST_MakePoint(
+                        (data->'route'->'origin'->'coordinates'->>'lng')::FLOAT8,
+                        (data->'route'->'origin'->'coordinates'->>'lat')::FLOAT8
+                    ),
+                    4326
+                )::geography,
+                ST_FlipCoordinates(ST_SetSRID($1, 4326))::geography,
+                $3
+            )
+        LIMIT 100
+    ";
+
+    let results = conn
+        .fetch_all(
+            sqlx::query(query)
+                .bind(wkb::Encode(origin_location))
+                .bind(route.distance)
+                .bind(search_radius)
+                .bind(distance_tolerance)
+                .bind(vehicle_class.name()),
+        )
+        .await?;
+
+    let mut fares = vec![];
+
+    for result in results.iter() {
+        let Json(fare): Json<FareBreakdown> = result.try_get("fare_breakdown")?;
+        fares.push(fare);
+    }
+
+    Ok(percentile(fares, 0.5))
+}
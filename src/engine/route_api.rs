@@ -8,8 +8,9 @@ use uuid::Uuid;
 use crate::{
     api::{LocationAPI, RouteAPI},
     auth::User,
-    entities::Route,
+    entities::{Coordinates, Route},
     error::{invalid_input_error, Error},
+    external::mapbox,
 };
 
 #[async_trait]
@@ -19,12 +20,41 @@ impl RouteAPI for Engine {
         &self,
         user: User,
         origin_token: Uuid,
+        stop_tokens: Vec<Uuid>,
         destination_token: Uuid,
     ) -> Result<Route, Error> {
         let origin = self.find_location(user.clone(), origin_token).await?;
         let destination = self.find_location(user.clone(), destination_token).await?;
 
-        let route = Route::new(origin, destination, json!(""), 4500.0);
+        let mut stops = vec![];
+        for token in stop_tokens.into_iter() {
+            stops.push(self.find_location(user.clone(), token).await?);
+        }
+
+        let mut route = Route::new(origin, destination, json!(""), 0.0).with_stops(stops);
+
+        let waypoints: Vec<Coordinates> = route
+            .waypoints()
+            .iter()
+            .map(|location| location.coordinates.clone())
+            .collect();
+
+        match mapbox::find_directions(&waypoints).await {
+            Ok(directions) => {
+                route.distance = directions.distance;
+                route.directions = json!(directions);
+            }
+            Err(err) => {
+                tracing::warn!("failed to find directions, estimating: {:?}", err);
+
+                // without directions, each leg is estimated by the straight-line distance
+                // between stops
+                route.distance = waypoints
+                    .windows(2)
+                    .map(|leg| leg[0].distance(&leg[1]))
+                    .sum();
+            }
+        }
 
         let mut conn = self.pool.acquire().await?;
         conn.execute(
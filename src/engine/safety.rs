@@ -0,0 +1,265 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::entities::{Coordinates, SafetyAlertKind, TracePoint};
+
+/// Thresholds of the safety monitor, with distances in meters, durations in seconds and the
+/// average speed in meters per second.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SafetyConfig {
+    // how far the vehicle may stray from the planned path
+    pub max_deviation: f64,
+    // how long the vehicle may stay within `stop_radius` of one spot
+    pub max_stop: i64,
+    pub stop_radius: f64,
+    // how many times the expected duration a trip may take
+    pub overdue_factor: f64,
+    pub average_speed: f64,
+    // how much longer than the straight-line distance the roads of routes without a path are
+    pub detour_factor: f64,
+}
+
+impl Default for SafetyConfig {
+    fn default() -> Self {
+        Self {
+            max_deviation: 500.0,
+            max_stop: 300,
+            stop_radius: 30.0,
+            overdue_factor: 2.0,
+            average_speed: 8.0,
+            detour_factor: 1.4,
+        }
+    }
+}
+
+impl SafetyConfig {
+    /// Checks the trace of a trip under way against its planned path and expected duration. Routes
+    /// without a path are not checked for deviations, and their straight-line distance is scaled
+    /// by the detour factor. Stops are only checked once the vehicle has left the pickup, as the
+    /// driver may wait there for the passenger.
+    pub fn check(
+        &self,
+        path: Option<&[Coordinates]>,
+        distance: f64,
+        trace: &[TracePoint],
+        pickup_time: DateTime<Utc>,
+        now: DateTime<Utc>,
+    ) -> Vec<SafetyAlertKind> {
+        let mut alerts = vec![];
+
+        if let (Some(path), Some(last)) = (path, trace.last()) {
+            let deviation = distance_to_path(&last.coordinates, path);
+
+            if deviation > self.max_deviation {
+                alerts.push(SafetyAlertKind::RouteDeviation {
+                    distance: deviation,
+                });
+            }
+        }
+
+        let departure = trace.first().and_then(|pickup| {
+            trace.iter().position(|point| {
+                point.coordinates.distance(&pickup.coordinates) > self.stop_radius
+            })
+        });
+
+        let stop = match departure {
+            Some(departure) => stop_duration(&trace[departure..], self.stop_radius),
+            None => 0,
+        };
+
+        if stop > self.max_stop {
+            alerts.push(SafetyAlertKind::UnexpectedStop { duration: stop });
+        }
+
+        let distance = match path {
+            Some(_) => distance,
+            None => distance * self.detour_factor,
+        };
+
+        let expected_duration = (distance / self.average_speed) as i64;
+        let duration = (now - pickup_time).num_seconds();
+
+        if duration as f64 > expected_duration as f64 * self.overdue_factor {
+            alerts.push(SafetyAlertKind::Overdue {
+                expected_duration,
+                duration,
+            });
+        }
+
+        alerts
+    }
+}
+
+/// Returns the shortest distance from a point to a path, in meters. Distances are computed on a
+/// local flat projection around the point, which is accurate enough at city scale.
+pub fn distance_to_path(point: &Coordinates, path: &[Coordinates]) -> f64 {
+    let project = |coordinates: &Coordinates| {
+        let scale = 6_371_000.0_f64.to_radians();
+        let x = (coordinates.lng - point.lng) * point.lat.to_radians().cos() * scale;
+        let y = (coordinates.lat - point.lat) * scale;
+        (x, y)
+    };
+
+    if path.len() == 1 {
+        return point.distance(&path[0]);
+    }
+
+    path.windows(2)
+        .map(|segment| {
+            let (ax, ay) = project(&segment[0]);
+            let (bx, by) = project(&segment[1]);
+            let (dx, dy) = (bx - ax, by - ay);
+
+            let length = dx * dx + dy * dy;
+            let t = match length > 0.0 {
+                true => (-(ax * dx + ay * dy) / length).clamp(0.0, 1.0),
+                false => 0.0,
+            };
+
+            (ax + t * dx).hypot(ay + t * dy)
+        })
+        .fold(f64::INFINITY, f64::min)
+}
+
+/// Returns for how long, in seconds, the trace has stayed within the radius of its last point.
+pub fn stop_duration(trace: &[TracePoint], radius: f64) -> i64 {
+    let last = match trace.last() {
+        Some(last) => last,
+        None => return 0,
+    };
+
+    let since = trace
+        .iter()
+        .rev()
+        .take_while(|point| point.coordinates.distance(&last.coordinates) <= radius)
+        .last()
+        .map_or(last.timestamp, |point| point.timestamp);
+
+    (last.timestamp - since).num_seconds()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::Duration;
+
+    fn point(lat: f64, lng: f64, seconds_ago: i64) -> TracePoint {
+        TracePoint {
+            coordinates: Coordinates { lat, lng },
+            timestamp: Utc::now() - Duration::seconds(seconds_ago),
+        }
+    }
+
+    #[test]
+    fn distance_to_path_test() {
+        let path = vec![
+            Coordinates { lat: 0.0, lng: 0.0 },
+            Coordinates { lat: 0.0, lng: 0.1 },
+        ];
+
+        let on_path = Coordinates {
+            lat: 0.0,
+            lng: 0.05,
+        };
+        assert!(distance_to_path(&on_path, &path) < 1.0);
+
+        // roughly 111 m per 0.001 degrees
+        let beside = Coordinates {
+            lat: 0.001,
+            lng: 0.05,
+        };
+        assert!((distance_to_path(&beside, &path) - 111.0).abs() < 1.0);
+
+        let beyond = Coordinates {
+            lat: 0.0,
+            lng: 0.101,
+        };
+        assert!((distance_to_path(&beyond, &path) - 111.0).abs() < 1.0);
+    }
+
+    #[test]
+    fn stop_duration_test() {
+        assert_eq!(stop_duration(&[], 30.0), 0);
+
+        let trace = vec![
+            point(0.0, 0.0, 600),
+            point(0.01, 0.0, 400),
+            point(0.0101, 0.0, 200),
+            point(0.01, 0.0, 0),
+        ];
+        assert_eq!(stop_duration(&trace, 30.0), 400);
+        assert_eq!(stop_duration(&trace, 5.0), 0);
+    }
+
+    #[test]
+    fn check_test() {
+        let config = SafetyConfig::default();
+        let path = vec![
+            Coordinates { lat: 0.0, lng: 0.0 },
+            Coordinates { lat: 0.0, lng: 0.1 },
+        ];
+        let now = Utc::now();
+
+        let trace = vec![point(0.0, 0.0, 60), point(0.0, 0.01, 0)];
+        let alerts = config.check(
+            Some(&path),
+            11_000.0,
+            &trace,
+            now - Duration::seconds(60),
+            now,
+        );
+        assert!(alerts.is_empty());
+
+        let trace = vec![point(0.0, 0.0, 600), point(0.01, 0.01, 0)];
+        let alerts = config.check(
+            Some(&path),
+            1000.0,
+            &trace,
+            now - Duration::seconds(600),
+            now,
+        );
+        assert_eq!(alerts.len(), 2);
+        assert_eq!(alerts[0].name(), "route_deviation");
+        assert_eq!(
+            alerts[1],
+            SafetyAlertKind::Overdue {
+                expected_duration: 125,
+                duration: 600
+            }
+        );
+
+        // routes without a path are only checked for stops and overdue arrival, allowing for
+        // detours
+        let alerts = config.check(None, 1000.0, &trace, now - Duration::seconds(600), now);
+        assert_eq!(
+            alerts,
+            vec![SafetyAlertKind::Overdue {
+                expected_duration: 175,
+                duration: 600
+            }]
+        );
+
+        // waiting for the passenger at the pickup is not a stop, but stopping after leaving is
+        let trace = vec![
+            point(0.0, 0.0, 900),
+            point(0.0, 0.0, 500),
+            point(0.0, 0.01, 400),
+        ];
+        let alerts = config.check(None, 11_000.0, &trace, now - Duration::seconds(900), now);
+        assert!(alerts.is_empty());
+
+        let trace = vec![
+            point(0.0, 0.0, 900),
+            point(0.0, 0.01, 800),
+            point(0.0, 0.01, 0),
+        ];
+        let alerts = config.check(None, 11_000.0, &trace, now - Duration::seconds(900), now);
+        assert_eq!(
+            alerts,
+            vec![SafetyAlertKind::UnexpectedStop { duration: 800 }]
+        );
+    }
+}
@@ -0,0 +1,150 @@
+use super::helpers::{fetch_trip_trace, insert_event};
+use super::Engine;
+
+use async_trait::async_trait;
+use chrono::{Duration, Utc};
+use sqlx::{types::Json, Acquire, Executor, Row};
+use uuid::Uuid;
+
+use crate::{
+    api::{SafetyAPI, TripAPI},
+    auth::{Platform, User},
+    entities::{DomainEvent, SafetyAlert, SafetyAlertKind, Trip},
+    error::Error,
+};
+
+#[async_trait]
+impl SafetyAPI for Engine {
+    #[tracing::instrument(skip(self))]
+    async fn find_safety_alerts(&self, user: User) -> Result<Vec<SafetyAlert>, Error> {
+        self.authorize(user, "read_safety_alerts", Platform::default())?;
+
+        let mut conn = self.pool.acquire().await?;
+
+        let results = conn
+            .fetch_all(
+                sqlx::query(
+                    "SELECT data FROM safety_alerts WHERE timestamp > $1 ORDER BY timestamp DESC",
+                )
+                .bind(Utc::now() - Duration::hours(24)),
+            )
+            .await?;
+
+        let mut alerts = vec![];
+
+        for result in results.iter() {
+            let Json(alert) = result.try_get("data")?;
+            alerts.push(alert);
+        }
+
+        Ok(alerts)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn find_trip_alerts(&self, user: User, id: Uuid) -> Result<Vec<SafetyAlert>, Error> {
+        let trip = self.find_trip(user.clone(), id).await?;
+
+        self.authorize(user, "read_safety_alerts", trip.clone())?;
+
+        let mut conn = self.pool.acquire().await?;
+
+        let results = conn
+            .fetch_all(
+                sqlx::query(
+                    "SELECT data FROM safety_alerts WHERE trip_id = $1 ORDER BY timestamp ASC",
+                )
+                .bind(trip.id),
+            )
+            .await?;
+
+        let mut alerts = vec![];
+
+        for result in results.iter() {
+            let Json(alert) = result.try_get("data")?;
+            alerts.push(alert);
+        }
+
+        Ok(alerts)
+    }
+}
+
+impl Engine {
+    /// Checks the trace of every trip under way for route deviations, unexpected stops and
+    /// overdue arrival. Each kind of alert is raised at most once per trip.
+    #[tracing::instrument(skip(self))]
+    pub async fn monitor_trips(&self) -> Result<(), Error> {
+        let mut conn = self.pool.acquire().await?;
+
+        let results = conn
+            .fetch_all(sqlx::query(
+                "SELECT data FROM trips WHERE status = 'driver_arrived'",
+            ))
+            .await?;
+
+        for result in results.iter() {
+            let Json(trip): Json<Trip> = result.try_get("data")?;
+
+            let (driver_id, pickup_time) = match (trip.driver_id, trip.pickup_time()) {
+                (Some(driver_id), Some(pickup_time)) => (driver_id, pickup_time),
+                _ => continue,
+            };
+
+            let trace = fetch_trip_trace(&mut conn, &trip.id, Some(pickup_time)).await?;
+
+            let last = match trace.points.last() {
+                Some(last) => last.coordinates.clone(),
+                None => continue,
+            };
+
+            let path = trip.route.path();
+
+            let kinds = self.safety.check(
+                path.as_deref(),
+                trip.route.distance,
+                &trace.points,
+                pickup_time,
+                Utc::now(),
+            );
+
+            // pooled trips deviate from their own route to serve the other passengers
+            let kinds = kinds.into_iter().filter(|kind| {
+                !(trip.is_pooled && matches!(kind, SafetyAlertKind::RouteDeviation { .. }))
+            });
+
+            for kind in kinds {
+                let alert = SafetyAlert::new(trip.id, driver_id, kind, last.clone());
+
+                let mut tx = conn.begin().await?;
+
+                let inserted = tx
+                    .execute(
+                        sqlx::query("INSERT INTO safety_alerts (id, trip_id, kind, timestamp, data) VALUES ($1, $2, $3, $4, $5) ON CONFLICT (trip_id, kind) DO NOTHING")
+                            .bind(alert.id)
+                            .bind(alert.trip_id)
+                            .bind(alert.kind.name())
+                            .bind(alert.timestamp)
+                            .bind(Json(&alert)),
+                    )
+                    .await?
+                    .rows_affected();
+
+                if inserted > 0 {
+                    tracing::warn!("raised {} alert for trip {}", alert.kind.name(), trip.id);
+
+                    insert_event(
+                        &mut tx,
+                        &DomainEvent::SafetyAlertRaised {
+                            trip_id: trip.id,
+                            alert,
+                        },
+                    )
+                    .await?;
+                }
+
+                tx.commit().await?;
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,61 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::error::{invalid_input_error, Error};
+
+/// Settings of trips booked in advance, in seconds.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SchedulingConfig {
+    // how long before pickup the search for a driver starts
+    pub lead_time: i64,
+    // how far in advance trips must and may be booked
+    pub min_advance: i64,
+    pub max_advance: i64,
+}
+
+impl Default for SchedulingConfig {
+    fn default() -> Self {
+        Self {
+            lead_time: 15 * 60,
+            min_advance: 30 * 60,
+            max_advance: 7 * 24 * 60 * 60,
+        }
+    }
+}
+
+impl SchedulingConfig {
+    pub fn ensure_schedulable(&self, pickup_at: DateTime<Utc>) -> Result<(), Error> {
+        let advance = (pickup_at - Utc::now()).num_seconds();
+
+        if advance < self.min_advance || advance > self.max_advance {
+            return Err(invalid_input_error());
+        }
+
+        Ok(())
+    }
+
+    /// Returns the latest pickup time of scheduled trips that are due for driver search.
+    pub fn dispatch_horizon(&self) -> DateTime<Utc> {
+        Utc::now() + Duration::seconds(self.lead_time)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ensure_schedulable_test() {
+        let config = SchedulingConfig::default();
+
+        assert!(config
+            .ensure_schedulable(Utc::now() + Duration::minutes(10))
+            .is_err());
+        assert!(config
+            .ensure_schedulable(Utc::now() + Duration::hours(2))
+            .is_ok());
+        assert!(config
+            .ensure_schedulable(Utc::now() + Duration::days(30))
+            .is_err());
+    }
+}
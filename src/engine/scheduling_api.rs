@@ -0,0 +1,262 @@
+use super::helpers::{
+    fetch_driver_for_update, fetch_driver_rate, fetch_passenger_for_update, fetch_trip_for_update,
+    insert_event, insert_trip_event, update_driver, update_passenger, update_trip,
+};
+use super::surge::geohash;
+use super::Engine;
+
+use async_trait::async_trait;
+use sqlx::{types::Json, Acquire, Executor, Row};
+use uuid::Uuid;
+
+use crate::{
+    api::{SchedulingAPI, TripAPI},
+    auth::User,
+    entities::{DomainEvent, ScheduledTripListing, Trip, TripStatus},
+    error::{invalid_invocation_error, Error},
+};
+
+#[async_trait]
+impl SchedulingAPI for Engine {
+    #[tracing::instrument(skip(self))]
+    async fn find_scheduled_trips(&self, user: User) -> Result<Vec<Trip>, Error> {
+        let mut conn = self.pool.acquire().await?;
+
+        let results = conn
+            .fetch_all(sqlx::query(
+                "SELECT data FROM trips WHERE status = 'scheduled' ORDER BY (data->'status'->>'pickup_at')::TIMESTAMPTZ ASC",
+            ))
+            .await?;
+
+        let mut trips = vec![];
+
+        for result in results.iter() {
+            let Json(trip): Json<Trip> = result.try_get("data")?;
+
+            if self.authorize(user.clone(), "read", trip.clone()).is_ok() {
+                trips.push(trip);
+            }
+        }
+
+        Ok(trips)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn find_scheduled_listings(
+        &self,
+        user: User,
+    ) -> Result<Vec<ScheduledTripListing>, Error> {
+        let mut conn = self.pool.acquire().await?;
+
+        let results = conn
+            .fetch_all(sqlx::query(
+                "SELECT data FROM trips WHERE status = 'scheduled' ORDER BY (data->'status'->>'pickup_at')::TIMESTAMPTZ ASC",
+            ))
+            .await?;
+
+        let mut listings = vec![];
+
+        for result in results.iter() {
+            let Json(trip): Json<Trip> = result.try_get("data")?;
+
+            if self.authorize(user.clone(), "list", trip.clone()).is_err() {
+                continue;
+            }
+
+            if let Some(listing) = listing_for(&trip) {
+                listings.push(listing);
+            }
+        }
+
+        Ok(listings)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn commit_to_trip(&self, user: User, id: Uuid) -> Result<Trip, Error> {
+        let mut conn = self.pool.acquire().await?;
+        let mut tx = conn.begin().await?;
+
+        let mut trip = fetch_trip_for_update(&mut tx, &id).await?;
+
+        self.authorize(user.clone(), "commit", trip.clone())?;
+
+        // drivers without a registered vehicle are taken to drive an economy vehicle
+        let maybe_driver = tx
+            .fetch_optional(
+                sqlx::query("SELECT d.id FROM drivers d LEFT JOIN vehicles v ON d.id = v.driver_id WHERE d.id = $1 AND COALESCE(v.class, 'economy') = $2")
+                    .bind(user.id)
+                    .bind(trip.vehicle_class.name()),
+            )
+            .await?;
+
+        if maybe_driver.is_none() {
+            return Err(invalid_invocation_error());
+        }
+
+        // the fare is agreed at the driver's current rate, excluding the pickup distance
+        let (min_fare, rate) = fetch_driver_rate(&mut tx, &user.id).await?;
+
+        let breakdown = self.adjust_fare(
+            self.route_fare(&trip.route, min_fare, rate, 0.0)
+                .with_surge(trip.surge_multiplier),
+            trip.vehicle_class,
+            trip.is_pooled,
+        );
+
+        let from_status = trip.status.clone();
+
+        trip.commit_driver(user.id, breakdown)?;
+
+        update_trip(&mut tx, &trip).await?;
+        insert_trip_event(&mut tx, &user.id, Some(&from_status), &trip).await?;
+        insert_event(
+            &mut tx,
+            &DomainEvent::DriverCommitted {
+                trip_id: trip.id,
+                driver_id: user.id,
+            },
+        )
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(trip)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn withdraw_from_trip(&self, user: User, id: Uuid) -> Result<Trip, Error> {
+        let mut conn = self.pool.acquire().await?;
+        let mut tx = conn.begin().await?;
+
+        let mut trip = fetch_trip_for_update(&mut tx, &id).await?;
+
+        self.authorize(user.clone(), "withdraw", trip.clone())?;
+
+        let from_status = trip.status.clone();
+
+        let is_late = trip.withdraw_driver(user.id)?;
+
+        update_trip(&mut tx, &trip).await?;
+        insert_trip_event(&mut tx, &user.id, Some(&from_status), &trip).await?;
+        insert_event(
+            &mut tx,
+            &DomainEvent::DriverWithdrew {
+                trip_id: trip.id,
+                driver_id: user.id,
+                is_late,
+            },
+        )
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(trip)
+    }
+}
+
+impl Engine {
+    /// Moves scheduled trips into driver search once their pickup is within the lead time. The
+    /// committed driver of a trip is requested first if still available, otherwise the trip is
+    /// searched for like any other. Trips whose passenger is on another trip are left scheduled.
+    #[tracing::instrument(skip(self))]
+    pub async fn dispatch_scheduled_trips(&self) -> Result<(), Error> {
+        let user = User::new_system_user();
+
+        let trip_ids: Vec<Uuid> = sqlx::query_scalar(
+            "SELECT id FROM trips WHERE status = 'scheduled' AND (data->'status'->>'pickup_at')::TIMESTAMPTZ <= $1",
+        )
+        .bind(self.scheduling.dispatch_horizon())
+        .fetch_all(&self.pool)
+        .await?;
+
+        for trip_id in trip_ids.into_iter() {
+            let trip = match self.dispatch_scheduled_trip(&user, trip_id).await {
+                Ok(trip) => trip,
+                Err(err) => {
+                    tracing::warn!("failed to dispatch scheduled trip {}: {:?}", trip_id, err);
+                    continue;
+                }
+            };
+
+            if trip.is_searching() {
+                if let Err(err) = self.request_driver(user.clone(), trip_id).await {
+                    tracing::warn!("failed to request driver for trip {}: {:?}", trip_id, err);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn dispatch_scheduled_trip(&self, user: &User, id: Uuid) -> Result<Trip, Error> {
+        let mut conn = self.pool.acquire().await?;
+        let mut tx = conn.begin().await?;
+
+        let mut trip = fetch_trip_for_update(&mut tx, &id).await?;
+
+        let from_status = trip.status.clone();
+
+        let committed = trip.start_search()?;
+
+        // the passenger only becomes active once the trip is dispatched. while the passenger is
+        // still on another trip, the trip stays scheduled and is dispatched on a later run
+        let mut passenger = fetch_passenger_for_update(&mut tx, &trip.passenger_id).await?;
+        passenger.activate(trip.id)?;
+        update_passenger(&mut tx, &passenger).await?;
+
+        update_trip(&mut tx, &trip).await?;
+        insert_trip_event(&mut tx, &user.id, Some(&from_status), &trip).await?;
+        insert_event(&mut tx, &DomainEvent::TripDispatched { trip_id: trip.id }).await?;
+
+        if let Some((driver_id, breakdown)) = committed {
+            let mut driver = fetch_driver_for_update(&mut tx, &driver_id).await?;
+
+            if driver.is_available() {
+                let from_status = trip.status.clone();
+                let fare = breakdown.total;
+
+                driver.request(trip.id)?;
+                trip.request_driver(driver_id, 0.0, breakdown)?;
+
+                update_driver(&mut tx, &driver).await?;
+                update_trip(&mut tx, &trip).await?;
+                insert_trip_event(&mut tx, &user.id, Some(&from_status), &trip).await?;
+                insert_event(
+                    &mut tx,
+                    &DomainEvent::DriverRequested {
+                        trip_id: trip.id,
+                        driver_id,
+                        fare,
+                    },
+                )
+                .await?;
+            } else {
+                tracing::warn!(
+                    "committed driver {} of trip {} is unavailable",
+                    driver_id,
+                    trip.id
+                );
+            }
+        }
+
+        tx.commit().await?;
+
+        Ok(trip)
+    }
+}
+
+/// Lists a scheduled trip without the passenger and the exact locations of the trip.
+fn listing_for(trip: &Trip) -> Option<ScheduledTripListing> {
+    let pickup_at = match trip.status {
+        TripStatus::Scheduled { pickup_at, .. } => pickup_at,
+        _ => return None,
+    };
+
+    Some(ScheduledTripListing {
+        trip_id: trip.id,
+        pickup_area: geohash(&trip.route.origin.coordinates, 5),
+        pickup_at,
+        fare: trip.max_fare,
+        vehicle_class: trip.vehicle_class,
+    })
+}
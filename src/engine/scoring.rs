@@ -0,0 +1,176 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::entities::DriverStats;
+
+/// Settings of how drivers are ranked for a trip. Each term of the score is scaled to at most
+/// one before it is weighted, with durations in seconds and distances in meters.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ScoringConfig {
+    pub acceptance_weight: f64,
+    pub cancellation_weight: f64,
+    pub punctuality_weight: f64,
+    pub rating_weight: f64,
+    pub idle_weight: f64,
+    pub distance_weight: f64,
+    // idle time beyond which drivers are favoured no further
+    pub max_idle: i64,
+    // pickup distance at which the distance term reaches its full weight
+    pub distance_scale: f64,
+    // the rating drivers are assumed to have until they are rated
+    pub prior_rating: f64,
+}
+
+impl Default for ScoringConfig {
+    fn default() -> Self {
+        Self {
+            acceptance_weight: 1.0,
+            cancellation_weight: 1.0,
+            punctuality_weight: 0.5,
+            rating_weight: 1.0,
+            idle_weight: 0.5,
+            distance_weight: 1.0,
+            max_idle: 1800,
+            distance_scale: 2000.0,
+            prior_rating: 4.0,
+        }
+    }
+}
+
+/// The weighted terms of the score of a driver for a trip, where higher is better.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DriverScore {
+    pub acceptance: f64,
+    pub cancellation: f64,
+    pub punctuality: f64,
+    pub rating: f64,
+    pub idle: f64,
+    pub distance: f64,
+    pub total: f64,
+}
+
+impl ScoringConfig {
+    /// Scores a driver from their stats, treating drivers without a recent trip as fully idle.
+    pub fn score(
+        &self,
+        stats: &DriverStats,
+        pickup_distance: f64,
+        now: DateTime<Utc>,
+    ) -> DriverScore {
+        // rates are smoothed so that drivers with few offers start out in between. offers that
+        // were withdrawn before the driver answered them count neither way
+        let answered = stats.acceptances + stats.rejections + stats.expirations;
+        let acceptance_rate = (stats.acceptances as f64 + 1.0) / (answered as f64 + 2.0);
+        let cancellation_rate = stats.cancellations as f64 / (stats.acceptances as f64 + 2.0);
+        let on_time_rate =
+            ((stats.arrivals - stats.late_arrivals) as f64 + 1.0) / (stats.arrivals as f64 + 2.0);
+
+        // ratings range from one to five, with the prior counted as a rating of its own
+        let rating = (stats.rating_total + self.prior_rating) / (stats.ratings as f64 + 1.0);
+
+        let idle = match stats.last_trip_at {
+            Some(last_trip_at) => {
+                let idle = (now - last_trip_at).num_seconds();
+                idle.clamp(0, self.max_idle) as f64 / self.max_idle as f64
+            }
+            None => 1.0,
+        };
+
+        let acceptance = self.acceptance_weight * acceptance_rate;
+        let cancellation = -self.cancellation_weight * cancellation_rate.min(1.0);
+        let punctuality = self.punctuality_weight * on_time_rate;
+        let rating = self.rating_weight * (rating - 1.0) / 4.0;
+        let idle = self.idle_weight * idle;
+        let distance = -self.distance_weight * pickup_distance / self.distance_scale;
+
+        DriverScore {
+            acceptance,
+            cancellation,
+            punctuality,
+            rating,
+            idle,
+            distance,
+            total: acceptance + cancellation + punctuality + rating + idle + distance,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::Duration;
+
+    #[test]
+    fn score_test() {
+        let config = ScoringConfig::default();
+        let now = Utc::now();
+
+        let new = config.score(&DriverStats::default(), 1000.0, now);
+
+        assert_eq!(new.acceptance, 0.5);
+        assert_eq!(new.cancellation, 0.0);
+        assert_eq!(new.punctuality, 0.25);
+        assert_eq!(new.rating, 0.75);
+        assert_eq!(new.idle, 0.5);
+        assert_eq!(new.distance, -0.5);
+        assert_eq!(new.total, 1.5);
+
+        let reliable = DriverStats {
+            offers: 20,
+            acceptances: 18,
+            rejections: 2,
+            arrivals: 18,
+            late_arrivals: 1,
+            ratings: 10,
+            rating_total: 48.0,
+            last_trip_at: Some(now - Duration::minutes(10)),
+            ..Default::default()
+        };
+        let unreliable = DriverStats {
+            offers: 20,
+            acceptances: 6,
+            expirations: 14,
+            cancellations: 3,
+            arrivals: 3,
+            late_arrivals: 2,
+            ratings: 10,
+            rating_total: 35.0,
+            last_trip_at: Some(now - Duration::minutes(10)),
+            ..Default::default()
+        };
+
+        assert!(
+            config.score(&reliable, 1000.0, now).total
+                > config.score(&unreliable, 1000.0, now).total
+        );
+
+        // a driver much closer to the pickup makes up for a weaker record
+        assert!(
+            config.score(&unreliable, 0.0, now).total > config.score(&reliable, 4000.0, now).total
+        );
+
+        // offers withdrawn after another driver accepted them do not lower the acceptance rate
+        let outraced = DriverStats {
+            offers: 20,
+            acceptances: 6,
+            ..Default::default()
+        };
+        let answered = DriverStats {
+            offers: 6,
+            ..outraced.clone()
+        };
+        assert_eq!(
+            config.score(&outraced, 1000.0, now).acceptance,
+            config.score(&answered, 1000.0, now).acceptance
+        );
+
+        // idle time only counts up to a limit
+        let idle = DriverStats {
+            last_trip_at: Some(now - Duration::hours(2)),
+            ..Default::default()
+        };
+        assert_eq!(config.score(&idle, 1000.0, now).idle, 0.5);
+    }
+}
@@ -0,0 +1,141 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::entities::{LocationFlagKind, LocationSample};
+
+/// Thresholds of the checks for spoofed driver locations, with speeds in meters per second and
+/// durations in seconds.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SpoofingConfig {
+    pub max_speed: f64,
+    // how many times in a row the exact same point may be reported
+    pub max_repeats: usize,
+    // whether drivers are withheld from dispatch while they have recent flags
+    pub withhold_flagged: bool,
+    pub withhold_duration: i64,
+}
+
+impl Default for SpoofingConfig {
+    fn default() -> Self {
+        Self {
+            max_speed: 70.0,
+            max_repeats: 30,
+            withhold_flagged: false,
+            withhold_duration: 900,
+        }
+    }
+}
+
+/// The last location a driver reported, which the next one is checked against.
+#[derive(Clone, Debug, Default)]
+pub struct Track {
+    last: Option<LocationSample>,
+    repeats: usize,
+}
+
+impl SpoofingConfig {
+    /// Checks a location against the last one reported by the driver, returning the flags
+    /// raised. Samples older than the last one are only checked for mock locations.
+    pub fn check(&self, track: &mut Track, sample: &LocationSample) -> Vec<LocationFlagKind> {
+        let mut flags = vec![];
+
+        if sample.is_mock {
+            flags.push(LocationFlagKind::MockLocation);
+        }
+
+        if let Some(last) = &track.last {
+            if sample.timestamp < last.timestamp {
+                return flags;
+            }
+
+            // samples are at least a second apart for the speed to be meaningful
+            let elapsed = (sample.timestamp - last.timestamp).num_milliseconds() as f64 / 1000.0;
+            let speed = last.coordinates.distance(&sample.coordinates) / elapsed.max(1.0);
+
+            if speed > self.max_speed {
+                flags.push(LocationFlagKind::ImplausibleSpeed { speed });
+            }
+
+            track.repeats = match sample.coordinates == last.coordinates {
+                true => track.repeats + 1,
+                false => 0,
+            };
+
+            // flagged once per run of repeats
+            if track.repeats == self.max_repeats {
+                flags.push(LocationFlagKind::RepeatedPoint {
+                    count: track.repeats + 1,
+                });
+            }
+        }
+
+        track.last = Some(sample.clone());
+
+        flags
+    }
+
+    /// Returns since when flags withhold drivers from dispatch, if they do.
+    pub fn withhold_since(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        match self.withhold_flagged {
+            true => Some(now - Duration::seconds(self.withhold_duration)),
+            false => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::entities::Coordinates;
+
+    fn sample(lng: f64, seconds: i64, now: DateTime<Utc>) -> LocationSample {
+        LocationSample {
+            coordinates: Coordinates { lat: 0.0, lng },
+            timestamp: now + Duration::seconds(seconds),
+            is_mock: false,
+        }
+    }
+
+    #[test]
+    fn check_test() {
+        let config = SpoofingConfig {
+            max_repeats: 2,
+            ..Default::default()
+        };
+        let now = Utc::now();
+        let mut track = Track::default();
+
+        assert!(config.check(&mut track, &sample(0.0, 0, now)).is_empty());
+
+        // roughly 111 m in 10 s
+        assert!(config.check(&mut track, &sample(0.001, 10, now)).is_empty());
+
+        // roughly 11 km in 10 s
+        let flags = config.check(&mut track, &sample(0.101, 20, now));
+        assert!(matches!(
+            flags.as_slice(),
+            [LocationFlagKind::ImplausibleSpeed { .. }]
+        ));
+
+        // out of order samples are not checked against the last one
+        assert!(config.check(&mut track, &sample(0.0, 15, now)).is_empty());
+
+        assert!(config.check(&mut track, &sample(0.101, 30, now)).is_empty());
+        assert_eq!(
+            config.check(&mut track, &sample(0.101, 40, now)),
+            vec![LocationFlagKind::RepeatedPoint { count: 3 }]
+        );
+        assert!(config.check(&mut track, &sample(0.101, 50, now)).is_empty());
+
+        let mock = LocationSample {
+            is_mock: true,
+            ..sample(0.1011, 60, now)
+        };
+        assert_eq!(
+            config.check(&mut track, &mock),
+            vec![LocationFlagKind::MockLocation]
+        );
+    }
+}
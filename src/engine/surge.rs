@@ -0,0 +1,241 @@
+use serde::{Deserialize, Serialize};
+
+use crate::entities::Coordinates;
+use crate::error::{invalid_input_error, Error};
+
+const BASE32: &[u8] = b"0123456789bcdefghjkmnpqrstuvwxyz";
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SurgeConfig {
+    // geohash precision of a cell, 6 is roughly 1.2 km x 0.6 km
+    pub precision: usize,
+    pub max_multiplier: f64,
+    // how strongly the multiplier responds to the demand/supply ratio
+    pub sensitivity: f64,
+    // weight of the latest target multiplier against the previous multiplier, in (0, 1]
+    pub smoothing: f64,
+}
+
+impl Default for SurgeConfig {
+    fn default() -> Self {
+        Self {
+            precision: 6,
+            max_multiplier: 3.0,
+            sensitivity: 0.5,
+            smoothing: 0.5,
+        }
+    }
+}
+
+impl SurgeConfig {
+    pub fn ensure_valid(&self) -> Result<(), Error> {
+        if !(self.smoothing > 0.0 && self.smoothing <= 1.0) {
+            return Err(invalid_input_error());
+        }
+
+        // negated so that NaN is rejected as well
+        if !(self.max_multiplier >= 1.0 && self.sensitivity >= 0.0) || self.precision < 1 {
+            return Err(invalid_input_error());
+        }
+
+        Ok(())
+    }
+
+    /// Returns the multiplier a cell should converge to given its demand and supply.
+    pub fn target_multiplier(&self, demand: i32, supply: i32) -> f64 {
+        if demand == 0 {
+            return 1.0;
+        }
+
+        let ratio = demand as f64 / supply.max(1) as f64;
+
+        (1.0 + self.sensitivity * (ratio - 1.0)).clamp(1.0, self.max_multiplier)
+    }
+
+    /// Moves the previous multiplier of a cell towards its target multiplier. Multipliers are
+    /// rounded to hundredths, so they snap to the target once within a hundredth of it, which
+    /// rounding alone would never reach.
+    pub fn smooth(&self, previous: f64, target: f64) -> f64 {
+        let multiplier = previous + self.smoothing * (target - previous);
+
+        if (target - multiplier).abs() < 0.01 {
+            return target;
+        }
+
+        (multiplier * 100.0).round() / 100.0
+    }
+}
+
+pub fn geohash(coordinates: &Coordinates, precision: usize) -> String {
+    let mut lat_range = (-90.0, 90.0);
+    let mut lng_range = (-180.0, 180.0);
+
+    let mut hash = String::with_capacity(precision);
+    let mut index = 0;
+    let mut bits = 0;
+    let mut is_lng = true;
+
+    while hash.len() < precision {
+        let (range, value) = match is_lng {
+            true => (&mut lng_range, coordinates.lng),
+            false => (&mut lat_range, coordinates.lat),
+        };
+
+        let mid = (range.0 + range.1) / 2.0;
+
+        index <<= 1;
+        if value >= mid {
+            index |= 1;
+            range.0 = mid;
+        } else {
+            range.1 = mid;
+        }
+
+        is_lng = !is_lng;
+        bits += 1;
+
+        if bits == 5 {
+            hash.push(BASE32[index] as char);
+            index = 0;
+            bits = 0;
+        }
+    }
+
+    hash
+}
+
+/// Returns the south-west and north-east corners of a geohash cell.
+pub fn geohash_bounds(hash: &str) -> Option<(Coordinates, Coordinates)> {
+    let mut lat_range = (-90.0, 90.0);
+    let mut lng_range = (-180.0, 180.0);
+    let mut is_lng = true;
+
+    for c in hash.bytes() {
+        let index = BASE32.iter().position(|&x| x == c)?;
+
+        for shift in (0..5).rev() {
+            let range = match is_lng {
+                true => &mut lng_range,
+                false => &mut lat_range,
+            };
+
+            let mid = (range.0 + range.1) / 2.0;
+
+            if (index >> shift) & 1 == 1 {
+                range.0 = mid;
+            } else {
+                range.1 = mid;
+            }
+
+            is_lng = !is_lng;
+        }
+    }
+
+    Some((
+        Coordinates {
+            lat: lat_range.0,
+            lng: lng_range.0,
+        },
+        Coordinates {
+            lat: lat_range.1,
+            lng: lng_range.1,
+        },
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn geohash_test() {
+        let coordinates = Coordinates {
+            lat: 57.64911,
+            lng: 10.40744,
+        };
+
+        assert_eq!(geohash(&coordinates, 11), "u4pruydqqvj");
+        assert_eq!(geohash(&coordinates, 6), "u4pruy");
+
+        let (min, max) = geohash_bounds("u4pruy").unwrap();
+        assert!(min.lat <= coordinates.lat && coordinates.lat <= max.lat);
+        assert!(min.lng <= coordinates.lng && coordinates.lng <= max.lng);
+
+        assert!(geohash_bounds("u4pruya").is_none());
+    }
+
+    #[test]
+    fn multiplier_test() {
+        let config = SurgeConfig::default();
+
+        assert_eq!(config.target_multiplier(0, 0), 1.0);
+        assert_eq!(config.target_multiplier(0, 10), 1.0);
+        assert_eq!(config.target_multiplier(5, 10), 1.0);
+        assert_eq!(config.target_multiplier(20, 10), 1.5);
+        assert_eq!(config.target_multiplier(100, 0), 3.0);
+
+        assert_eq!(config.smooth(1.0, 2.0), 1.5);
+        assert_eq!(config.smooth(1.5, 2.0), 1.75);
+        assert_eq!(config.smooth(1.75, 1.0), 1.38);
+
+        // weak smoothing still settles back to the target
+        let config = SurgeConfig {
+            smoothing: 0.3,
+            ..Default::default()
+        };
+
+        let mut multiplier = 1.5;
+        for _ in 0..20 {
+            multiplier = config.smooth(multiplier, 1.0);
+        }
+        assert_eq!(multiplier, 1.0);
+
+        assert!(config.ensure_valid().is_ok());
+
+        for smoothing in [0.0, -0.5, 1.5, f64::NAN] {
+            let config = SurgeConfig {
+                smoothing,
+                ..Default::default()
+            };
+            assert!(config.ensure_valid().is_err());
+        }
+    }
+
+    #[test]
+    fn invalid_config_test() {
+        let configs = [
+            SurgeConfig {
+                max_multiplier: 0.8,
+                ..Default::default()
+            },
+            SurgeConfig {
+                max_multiplier: f64::NAN,
+                ..Default::default()
+            },
+            SurgeConfig {
+                sensitivity: -0.5,
+                ..Default::default()
+            },
+            SurgeConfig {
+                sensitivity: f64::NAN,
+                ..Default::default()
+            },
+            SurgeConfig {
+                precision: 0,
+                ..Default::default()
+            },
+        ];
+
+        for config in configs {
+            assert!(config.ensure_valid().is_err());
+        }
+
+        let config = SurgeConfig {
+            max_multiplier: 1.0,
+            sensitivity: 0.0,
+            precision: 1,
+            ..Default::default()
+        };
+        assert!(config.ensure_valid().is_ok());
+    }
+}
@@ -0,0 +1,165 @@
+use super::surge::{geohash, geohash_bounds};
+use super::Engine;
+
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use sqlx::{Acquire, Executor, Row};
+
+use crate::{
+    api::SurgeAPI,
+    auth::{Platform, User},
+    entities::{Coordinates, SurgeCell},
+    error::Error,
+};
+
+#[async_trait]
+impl SurgeAPI for Engine {
+    #[tracing::instrument(skip(self))]
+    async fn find_surge(&self, user: User) -> Result<Vec<SurgeCell>, Error> {
+        self.authorize(user, "read_surge", Platform::default())?;
+
+        let mut conn = self.pool.acquire().await?;
+
+        let results = conn
+            .fetch_all(sqlx::query(
+                "SELECT cell, demand, supply, multiplier, updated_at FROM surge_cells",
+            ))
+            .await?;
+
+        let mut cells = vec![];
+
+        for result in results.iter() {
+            let cell: String = result.try_get("cell")?;
+
+            let (min, max) = match geohash_bounds(&cell) {
+                Some(bounds) => bounds,
+                None => continue,
+            };
+
+            cells.push(SurgeCell {
+                cell,
+                min,
+                max,
+                demand: result.try_get("demand")?,
+                supply: result.try_get("supply")?,
+                multiplier: result.try_get("multiplier")?,
+                updated_at: result.try_get("updated_at")?,
+            });
+        }
+
+        Ok(cells)
+    }
+}
+
+impl Engine {
+    /// Returns the current surge multiplier of the cell containing the coordinates.
+    #[tracing::instrument(skip(self))]
+    pub async fn surge_multiplier(&self, coordinates: &Coordinates) -> Result<f64, Error> {
+        let cell = geohash(coordinates, self.surge.precision);
+
+        let maybe_multiplier: Option<f64> =
+            sqlx::query_scalar("SELECT multiplier FROM surge_cells WHERE cell = $1")
+                .bind(cell)
+                .fetch_optional(&self.pool)
+                .await?;
+
+        Ok(maybe_multiplier.unwrap_or(1.0))
+    }
+
+    /// Recomputes the demand, supply and smoothed multiplier of every cell.
+    #[tracing::instrument(skip(self))]
+    pub async fn update_surge(&self) -> Result<(), Error> {
+        let mut conn = self.pool.acquire().await?;
+
+        let drivers_query = "
+            SELECT
+                ST_X(l.location) AS lat,
+                ST_Y(l.location) AS lng
+            FROM
+                drivers d
+                LEFT JOIN driver_locations l ON d.id = l.driver_id
+            WHERE
+                d.status = 'available'
+                AND l.location IS NOT NULL
+                AND l.expiry > now()
+        ";
+
+        let trips_query = "
+            SELECT
+                (data->'route'->'origin'->'coordinates'->>'lat')::FLOAT8 AS lat,
+                (data->'route'->'origin'->'coordinates'->>'lng')::FLOAT8 AS lng
+            FROM
+                trips
+            WHERE
+                status = 'searching'
+        ";
+
+        // (demand, supply) per cell
+        let mut counts: HashMap<String, (i32, i32)> = HashMap::new();
+
+        for result in conn.fetch_all(sqlx::query(trips_query)).await?.iter() {
+            let coordinates = Coordinates {
+                lat: result.try_get("lat")?,
+                lng: result.try_get("lng")?,
+            };
+
+            let cell = geohash(&coordinates, self.surge.precision);
+            counts.entry(cell).or_default().0 += 1;
+        }
+
+        for result in conn.fetch_all(sqlx::query(drivers_query)).await?.iter() {
+            let coordinates = Coordinates {
+                lat: result.try_get("lat")?,
+                lng: result.try_get("lng")?,
+            };
+
+            let cell = geohash(&coordinates, self.surge.precision);
+            counts.entry(cell).or_default().1 += 1;
+        }
+
+        let mut tx = conn.begin().await?;
+
+        let previous: Vec<(String, f64)> =
+            sqlx::query_as("SELECT cell, multiplier FROM surge_cells FOR UPDATE")
+                .fetch_all(&mut tx)
+                .await?;
+
+        for (cell, _) in previous.iter() {
+            counts.entry(cell.clone()).or_default();
+        }
+
+        let previous: HashMap<String, f64> = previous.into_iter().collect();
+        let updated_at: DateTime<Utc> = Utc::now();
+
+        tx.execute("DELETE FROM surge_cells").await?;
+
+        for (cell, (demand, supply)) in counts.into_iter() {
+            let target = self.surge.target_multiplier(demand, supply);
+            let previous = previous.get(&cell).copied().unwrap_or(1.0);
+            let multiplier = self.surge.smooth(previous, target);
+
+            // cells without activity are dropped once they have settled back to no surge
+            if demand == 0 && supply == 0 && multiplier <= 1.0 {
+                continue;
+            }
+
+            tx.execute(
+                sqlx::query(
+                    "INSERT INTO surge_cells (cell, demand, supply, multiplier, updated_at) VALUES ($1, $2, $3, $4, $5)",
+                )
+                .bind(cell)
+                .bind(demand)
+                .bind(supply)
+                .bind(multiplier)
+                .bind(updated_at),
+            )
+            .await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+}
@@ -1,36 +1,86 @@
 use super::helpers::{
-    fetch_driver_for_update, fetch_passenger_for_update, fetch_trip_for_update, update_driver,
-    update_passenger, update_trip,
+    fetch_driver_for_update, fetch_driver_rate, fetch_passenger_for_update, fetch_quote_for_update,
+    fetch_trip_for_update, fetch_trip_trace, insert_event, insert_trip_event, record_driver_stat,
+    subscription, update_driver, update_passenger, update_quote, update_trip, DriverStat,
 };
-use super::{Database, Engine};
+use super::{Database, DispatchConfig, Engine};
 
 use async_trait::async_trait;
+use chrono::Utc;
+use geo_types::Geometry;
+use geozero::wkb;
 use sqlx::{types::Json, Acquire, Executor, Row, Transaction};
+use tokio::sync::{broadcast::error::RecvError, mpsc};
 use uuid::Uuid;
 
-use crate::api::DriverSearchAPI;
+use crate::api::{DriverSearchAPI, RouteAPI};
 use crate::{
-    api::{QuoteAPI, TripAPI},
+    api::{Subscription, TripAPI},
     auth::{Platform, User},
-    entities::Trip,
+    entities::{
+        BroadcastOffer, Coordinates, DomainEvent, Driver, DriverStatus, FareBreakdown, MeteredFare,
+        OutboxEvent, PoolStopKind, Trip, TripEvent, TripStatus, TripTrace, TripUpdate,
+    },
     error::{invalid_input_error, invalid_invocation_error, Error},
 };
 
 #[async_trait]
 impl TripAPI for Engine {
     #[tracing::instrument(skip(self))]
-    async fn create_trip(&self, user: User, quote_token: Uuid) -> Result<Trip, Error> {
+    async fn create_trip(
+        &self,
+        user: User,
+        quote_token: Uuid,
+        wait_for_driver: bool,
+    ) -> Result<Trip, Error> {
         self.authorize(user.clone(), "create_trip", Platform::default())?;
 
         let mut conn = self.pool.acquire().await?;
         let mut tx = conn.begin().await?;
 
-        let quote = self.find_quote(user.clone(), quote_token).await?;
-        let trip = Trip::new(user.id.clone(), quote.route, quote.max_fare);
+        let mut quote = fetch_quote_for_update(&mut tx, &quote_token).await?;
 
-        // ensure passenger does not have another active trip while trip is created
+        self.authorize(user.clone(), "book", quote.clone())?;
+
+        // estimates are only bookable by passengers willing to wait for a driver to appear
+        if quote.is_estimate && !wait_for_driver {
+            return Err(invalid_invocation_error());
+        }
+
+        let trip = match quote.pickup_at {
+            Some(pickup_at) => Trip::new_scheduled(
+                user.id,
+                quote.route.clone(),
+                quote.max_fare,
+                quote.breakdown.surge_multiplier,
+                pickup_at,
+            ),
+            None => Trip {
+                wait_for_driver,
+                is_pooled: quote.is_pooled,
+                ..Trip::new(
+                    user.id,
+                    quote.route.clone(),
+                    quote.max_fare,
+                    quote.breakdown.surge_multiplier,
+                )
+            },
+        };
+
+        let trip = Trip {
+            vehicle_class: quote.vehicle_class,
+            ..trip
+        };
+
+        // fails if the quote has expired or was already used to create a trip
+        quote.consume(trip.id)?;
+
+        // ensure passenger does not have another active trip while trip is created, passengers
+        // of scheduled trips only become active once the trip is dispatched
         let mut passenger = fetch_passenger_for_update(&mut tx, &trip.passenger_id).await?;
-        passenger.activate(trip.id.clone())?;
+        if quote.pickup_at.is_none() {
+            passenger.activate(trip.id)?;
+        }
 
         tx.execute(
             sqlx::query("INSERT INTO trips (id, status, data) VALUES ($1, $2, $3)")
@@ -40,7 +90,17 @@ impl TripAPI for Engine {
         )
         .await?;
 
+        insert_trip_event(&mut tx, &user.id, None, &trip).await?;
+        insert_event(
+            &mut tx,
+            &DomainEvent::TripCreated {
+                trip_id: trip.id,
+                passenger_id: trip.passenger_id,
+            },
+        )
+        .await?;
         update_passenger(&mut tx, &passenger).await?;
+        update_quote(&mut tx, &quote).await?;
 
         tx.commit().await?;
 
@@ -63,6 +123,132 @@ impl TripAPI for Engine {
         Ok(trip)
     }
 
+    #[tracing::instrument(skip(self))]
+    async fn find_trip_history(&self, user: User, id: Uuid) -> Result<Vec<TripEvent>, Error> {
+        let trip = self.find_trip(user, id).await?;
+
+        let mut conn = self.pool.acquire().await?;
+
+        let results = conn
+            .fetch_all(
+                sqlx::query("SELECT id, trip_id, actor_id, from_status, to_status, timestamp, payload FROM trip_events WHERE trip_id = $1 ORDER BY id ASC")
+                    .bind(trip.id),
+            )
+            .await?;
+
+        let mut events = vec![];
+
+        for result in results.iter() {
+            let Json(payload) = result.try_get("payload")?;
+
+            events.push(TripEvent {
+                id: result.try_get("id")?,
+                trip_id: result.try_get("trip_id")?,
+                actor_id: result.try_get("actor_id")?,
+                from_status: result.try_get("from_status")?,
+                to_status: result.try_get("to_status")?,
+                timestamp: result.try_get("timestamp")?,
+                payload,
+            });
+        }
+
+        Ok(events)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn find_trip_trace(&self, user: User, id: Uuid) -> Result<TripTrace, Error> {
+        let trip = self.find_trip(user, id).await?;
+
+        let mut conn = self.pool.acquire().await?;
+
+        fetch_trip_trace(&mut conn, &trip.id, None).await
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn subscribe_trip(
+        &self,
+        user: User,
+        id: Uuid,
+    ) -> Result<Subscription<TripUpdate>, Error> {
+        // subscribe before fetching the trip so that no updates are missed in between
+        let mut events = self.subscribe();
+        let mut locations = self.locations.subscribe();
+
+        let trip = self.find_trip(user.clone(), id).await?;
+
+        // safety alerts are only pushed to the passenger and system users
+        let can_read_alerts = self
+            .authorize(user.clone(), "read_safety_alerts", trip.clone())
+            .is_ok();
+
+        let (sender, receiver) = mpsc::channel(16);
+        let engine = self.shared();
+
+        tokio::spawn(async move {
+            let mut driver_id = trip.driver_id;
+            let mut is_finished = trip.is_finished();
+
+            if sender
+                .send(TripUpdate::Status {
+                    trip: Box::new(trip),
+                })
+                .await
+                .is_err()
+            {
+                return;
+            }
+
+            while !is_finished {
+                let update = tokio::select! {
+                    event = events.recv() => {
+                        match event {
+                            Ok(event) if event.event.trip_id() != Some(id) => continue,
+                            Ok(OutboxEvent {
+                                event: DomainEvent::SafetyAlertRaised { alert, .. },
+                                ..
+                            }) => match can_read_alerts {
+                                true => {
+                                    if sender.send(TripUpdate::SafetyAlert { alert }).await.is_err() {
+                                        break;
+                                    }
+                                    continue;
+                                }
+                                false => continue,
+                            },
+                            // on lag the trip is refetched as status changes may have been missed
+                            Ok(_) | Err(RecvError::Lagged(_)) => {}
+                            Err(RecvError::Closed) => break,
+                        }
+
+                        // ends the subscription once the user may no longer read the trip
+                        match engine.find_trip(user.clone(), id).await {
+                            Ok(trip) => {
+                                driver_id = trip.driver_id;
+                                is_finished = trip.is_finished();
+                                TripUpdate::Status { trip: Box::new(trip) }
+                            }
+                            Err(_) => break,
+                        }
+                    }
+                    location = locations.recv() => match location {
+                        Ok(location) if Some(location.driver_id) == driver_id => {
+                            TripUpdate::DriverLocation { location }
+                        }
+                        Ok(_) | Err(RecvError::Lagged(_)) => continue,
+                        Err(RecvError::Closed) => break,
+                    },
+                    _ = sender.closed() => break,
+                };
+
+                if sender.send(update).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(subscription(receiver))
+    }
+
     #[tracing::instrument(skip(self))]
     async fn request_driver(&self, user: User, id: Uuid) -> Result<Option<Trip>, Error> {
         let mut conn = self.pool.acquire().await?;
@@ -85,56 +271,35 @@ impl TripAPI for Engine {
             return Err(invalid_invocation_error());
         }
 
+        // pooled trips first try to join a driver already serving a pool
+        if trip.is_pooled {
+            if let Some(trip) = self.join_pool(&user, &trip).await? {
+                return Ok(Some(trip));
+            }
+        }
+
+        // trips are left searching for the batch matcher to match them with the others
+        if let DispatchConfig::Batch(_) = self.dispatch {
+            return Ok(None);
+        }
+
         // find drivers
         let drivers = self.find_drivers(user.clone(), trip.clone()).await?;
 
+        if let DispatchConfig::Broadcast { size } = self.dispatch {
+            return self.broadcast_trip(&user, id, drivers, size).await;
+        }
+
         tracing::info!(
             "iterating through drivers to find a driver that satisfies all conditions..."
         );
 
         for (driver_id, distance) in drivers.into_iter() {
-            let mut tx = conn.begin().await?;
-
-            let mut trip = fetch_trip_for_update(&mut tx, &id).await?;
-            let mut driver = fetch_driver_for_update(&mut tx, &driver_id).await?;
-
-            if !driver.is_available() {
-                continue;
-            }
-
-            let (min_fare, rate): (f64, f64) = sqlx::query_as(
-                "SELECT min_fare, rate FROM driver_rates WHERE driver_id = $1 FOR UPDATE",
-            )
-            .bind(&driver.id)
-            .fetch_one(&mut tx)
-            .await?;
-
-            let fare = f64::max(min_fare, (distance + trip.route.distance) * rate);
-
-            if fare > trip.max_fare {
-                continue;
-            }
+            if let Some(trip) = self.offer_trip(&user, id, driver_id, distance).await? {
+                tracing::info!("successfully requested driver, returning...");
 
-            let maybe_trip_rejection = sqlx::query("SELECT driver_id FROM trip_rejections WHERE trip_id = $1 AND driver_id = $2 FOR UPDATE").bind(&trip.id).bind(&driver.id).fetch_optional(&mut tx).await?;
-            if maybe_trip_rejection.is_some() {
-                continue;
+                return Ok(Some(trip));
             }
-
-            tracing::info!(
-                "driver satisfies all conditions, attempting to update trip and driver..."
-            );
-
-            driver.request(trip.id.clone())?;
-            trip.request_driver(driver_id.clone(), fare)?;
-
-            update_driver(&mut tx, &driver).await?;
-            update_trip(&mut tx, &trip).await?;
-
-            tx.commit().await?;
-
-            tracing::info!("successfully requested driver, returning...");
-
-            return Ok(Some(trip));
         }
 
         tracing::warn!(
@@ -152,7 +317,7 @@ impl TripAPI for Engine {
 
         self.authorize(user.clone(), "release_driver", trip.clone())?;
 
-        release_driver(&mut tx, &mut trip, driver_id, false).await?;
+        release_driver(&mut tx, &user, &mut trip, driver_id, false).await?;
 
         tx.commit().await?;
 
@@ -165,21 +330,59 @@ impl TripAPI for Engine {
 
         let mut trip = fetch_trip_for_update(&mut tx, &id).await?;
 
-        self.authorize(user.clone(), "accept_trip", trip.clone())?;
+        self.authorize(user.clone(), "accept", trip.clone())?;
 
-        trip.assign_driver()?;
+        let from_status = trip.status.clone();
+
+        // the offer of a broadcast trip is withdrawn from the drivers who did not accept first
+        let withdrawn = match trip.status {
+            TripStatus::Broadcast { .. } => trip.accept_offer(user.id)?,
+            _ => {
+                trip.assign_driver()?;
+                vec![]
+            }
+        };
 
         let mut driver = fetch_driver_for_update(&mut tx, &user.id).await?;
 
-        driver.assign()?;
+        // the first pooled trip of a driver starts a pool that later trips may join
+        match trip.is_pooled {
+            true => {
+                let (pickup, dropoff) = trip.pool_stops();
+                driver.assign_pooled(vec![pickup, dropoff])?;
+            }
+            false => driver.assign()?,
+        }
 
         update_trip(&mut tx, &trip).await?;
         update_driver(&mut tx, &driver).await?;
+        insert_trip_event(&mut tx, &user.id, Some(&from_status), &trip).await?;
+        insert_event(
+            &mut tx,
+            &DomainEvent::TripAccepted {
+                trip_id: trip.id,
+                driver_id: driver.id,
+            },
+        )
+        .await?;
+
+        record_driver_stat(&mut tx, driver.id, DriverStat::Acceptance).await?;
+
+        // withdrawn offers do not count as rejections
+        for driver_id in withdrawn.into_iter() {
+            let mut driver = fetch_driver_for_update(&mut tx, &driver_id).await?;
+            driver.free()?;
 
-        sqlx::query("UPDATE driver_priorities SET priority = GREATEST(0, priority - 1) WHERE driver_id = $1")
-            .bind(&driver.id)
-            .execute(&mut tx)
+            update_driver(&mut tx, &driver).await?;
+            insert_event(
+                &mut tx,
+                &DomainEvent::OfferWithdrawn {
+                    trip_id: trip.id,
+                    driver_id,
+                },
+            )
             .await?;
+        }
 
         tx.commit().await?;
 
@@ -192,9 +395,9 @@ impl TripAPI for Engine {
 
         let mut trip = fetch_trip_for_update(&mut tx, &id).await?;
 
-        self.authorize(user.clone(), "reject_trip", trip.clone())?;
+        self.authorize(user.clone(), "reject", trip.clone())?;
 
-        release_driver(&mut tx, &mut trip, user.id.clone(), true).await?;
+        release_driver(&mut tx, &user, &mut trip, user.id, true).await?;
 
         tx.commit().await?;
 
@@ -207,25 +410,49 @@ impl TripAPI for Engine {
 
         let mut trip = fetch_trip_for_update(&mut tx, &id).await?;
 
-        self.authorize(user.clone(), "cancel_trip", trip.clone())?;
+        self.authorize(user.clone(), "cancel", trip.clone())?;
 
         let is_passenger = user.id == trip.passenger_id;
 
-        let freed_driver = trip.cancel(is_passenger)?;
+        let from_status = trip.status.clone();
+
+        let freed_drivers = trip.cancel(is_passenger)?;
 
         update_trip(&mut tx, &trip).await?;
+        insert_trip_event(&mut tx, &user.id, Some(&from_status), &trip).await?;
+
+        let penalty_bearer = match &trip.status {
+            TripStatus::Cancelled { penalty_bearer } => penalty_bearer.clone(),
+            _ => None,
+        };
+
+        insert_event(
+            &mut tx,
+            &DomainEvent::TripCancelled {
+                trip_id: trip.id,
+                cancelled_by: user.id,
+                penalty_bearer,
+            },
+        )
+        .await?;
 
-        if let Some(driver_id) = freed_driver {
+        for driver_id in freed_drivers.into_iter() {
             let mut driver = fetch_driver_for_update(&mut tx, &driver_id).await?;
-            driver.free()?;
+            driver.release_trip(&trip.id)?;
 
             update_driver(&mut tx, &driver).await?;
         }
 
+        if !is_passenger {
+            record_driver_stat(&mut tx, user.id, DriverStat::Cancellation).await?;
+        }
+
         let mut passenger = fetch_passenger_for_update(&mut tx, &trip.passenger_id).await?;
-        passenger.deactivate()?;
+        if passenger.is_active_in(&trip.id) {
+            passenger.deactivate()?;
 
-        update_passenger(&mut tx, &passenger).await?;
+            update_passenger(&mut tx, &passenger).await?;
+        }
 
         tx.commit().await?;
 
@@ -242,9 +469,53 @@ impl TripAPI for Engine {
 
         // TODO (umran) verify driver location is within accepted range of origin
 
+        let from_status = trip.status.clone();
+
         trip.begin_route()?;
 
+        if let TripStatus::DriverArrived { is_late, .. } = trip.status {
+            record_driver_stat(&mut tx, user.id, DriverStat::Arrival { is_late }).await?;
+        }
+
+        if trip.is_pooled {
+            let mut driver = fetch_driver_for_update(&mut tx, &user.id).await?;
+            driver.visit_stop(&trip.id, PoolStopKind::Pickup);
+
+            update_driver(&mut tx, &driver).await?;
+        }
+
+        update_trip(&mut tx, &trip).await?;
+        insert_trip_event(&mut tx, &user.id, Some(&from_status), &trip).await?;
+        insert_event(&mut tx, &DomainEvent::OriginArrived { trip_id: trip.id }).await?;
+
+        tx.commit().await?;
+
+        Ok(trip)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn report_stop_arrival(&self, user: User, id: Uuid) -> Result<Trip, Error> {
+        let mut conn = self.pool.acquire().await?;
+        let mut tx = conn.begin().await?;
+
+        let mut trip = fetch_trip_for_update(&mut tx, &id).await?;
+
+        self.authorize(user.clone(), "report_stop_arrival", trip.clone())?;
+
+        let from_status = trip.status.clone();
+
+        let stop = trip.reach_stop()?;
+
         update_trip(&mut tx, &trip).await?;
+        insert_trip_event(&mut tx, &user.id, Some(&from_status), &trip).await?;
+        insert_event(
+            &mut tx,
+            &DomainEvent::StopArrived {
+                trip_id: trip.id,
+                stop,
+            },
+        )
+        .await?;
 
         tx.commit().await?;
 
@@ -259,9 +530,163 @@ impl TripAPI for Engine {
 
         self.authorize(user.clone(), "report_destination_arrival", trip.clone())?;
 
+        let from_status = trip.status.clone();
+
+        // pooled trips are charged their upfront fare as the trace includes the detours made
+        // for the other passengers
+        if self.metering.enabled && !trip.is_pooled {
+            trip.metered_fare = self.meter_fare(&mut tx, &trip).await?;
+        }
+
         trip.end_route()?;
 
+        // the driver is free for other trips once no pooled trips remain
+        let mut driver = fetch_driver_for_update(&mut tx, &user.id).await?;
+        driver.release_trip(&trip.id)?;
+
+        update_driver(&mut tx, &driver).await?;
+
+        record_driver_stat(&mut tx, driver.id, DriverStat::Completion).await?;
+
+        let mut passenger = fetch_passenger_for_update(&mut tx, &trip.passenger_id).await?;
+        if passenger.is_active_in(&trip.id) {
+            passenger.deactivate()?;
+
+            update_passenger(&mut tx, &passenger).await?;
+        }
+
+        update_trip(&mut tx, &trip).await?;
+        insert_trip_event(&mut tx, &user.id, Some(&from_status), &trip).await?;
+        insert_event(&mut tx, &DomainEvent::TripCompleted { trip_id: trip.id }).await?;
+
+        tx.commit().await?;
+
+        Ok(trip)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn change_destination(
+        &self,
+        user: User,
+        id: Uuid,
+        stop_tokens: Option<Vec<Uuid>>,
+        destination_token: Uuid,
+    ) -> Result<Trip, Error> {
+        let trip = self.find_trip(user.clone(), id).await?;
+
+        // authorize before creating the route, and again once the trip is locked
+        self.authorize(user.clone(), "change_destination", trip.clone())?;
+
+        // the stops of the trip are kept on the way to the new destination unless they are
+        // changed as well
+        let stop_tokens =
+            stop_tokens.unwrap_or_else(|| trip.route.stops.iter().map(|stop| stop.token).collect());
+
+        let route = self
+            .create_route(
+                user.clone(),
+                trip.route.origin.token,
+                stop_tokens,
+                destination_token,
+            )
+            .await?;
+
+        let mut conn = self.pool.acquire().await?;
+        let mut tx = conn.begin().await?;
+
+        let mut trip = fetch_trip_for_update(&mut tx, &id).await?;
+
+        self.authorize(user.clone(), "change_destination", trip.clone())?;
+
+        // the new route is priced at the driver's rate, as the fare was when they were assigned
+        let driver_id = trip.driver_id.ok_or_else(invalid_invocation_error)?;
+        let (min_fare, rate) = fetch_driver_rate(&mut tx, &driver_id).await?;
+
+        let breakdown = self.adjust_fare(
+            self.route_fare(&route, min_fare, rate, trip.pickup_distance)
+                .with_surge(trip.surge_multiplier),
+            trip.vehicle_class,
+            trip.is_pooled,
+        );
+
+        let from_status = trip.status.clone();
+
+        trip.request_route_change(route, breakdown)?;
+
+        let change = trip
+            .pending_route_change
+            .as_ref()
+            .ok_or_else(invalid_invocation_error)?;
+
+        let event = DomainEvent::RouteChangeRequested {
+            trip_id: trip.id,
+            previous_fare: change.previous_fare,
+            fare: change.fare,
+        };
+
         update_trip(&mut tx, &trip).await?;
+        insert_trip_event(&mut tx, &user.id, Some(&from_status), &trip).await?;
+        insert_event(&mut tx, &event).await?;
+
+        tx.commit().await?;
+
+        Ok(trip)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn acknowledge_route_change(&self, user: User, id: Uuid) -> Result<Trip, Error> {
+        let mut conn = self.pool.acquire().await?;
+        let mut tx = conn.begin().await?;
+
+        let mut trip = fetch_trip_for_update(&mut tx, &id).await?;
+
+        self.authorize(user.clone(), "acknowledge_route_change", trip.clone())?;
+
+        let from_status = trip.status.clone();
+
+        trip.acknowledge_route_change()?;
+
+        update_trip(&mut tx, &trip).await?;
+        insert_trip_event(&mut tx, &user.id, Some(&from_status), &trip).await?;
+        insert_event(
+            &mut tx,
+            &DomainEvent::RouteChangeAcknowledged {
+                trip_id: trip.id,
+                driver_id: user.id,
+            },
+        )
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(trip)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn rate_trip(&self, user: User, id: Uuid, rating: i32) -> Result<Trip, Error> {
+        let mut conn = self.pool.acquire().await?;
+        let mut tx = conn.begin().await?;
+
+        let mut trip = fetch_trip_for_update(&mut tx, &id).await?;
+
+        self.authorize(user.clone(), "rate", trip.clone())?;
+
+        let driver_id = trip.driver_id.ok_or_else(invalid_invocation_error)?;
+
+        trip.rate(rating)?;
+
+        update_trip(&mut tx, &trip).await?;
+        insert_event(
+            &mut tx,
+            &DomainEvent::TripRated {
+                trip_id: trip.id,
+                driver_id,
+                rating,
+            },
+        )
+        .await?;
+
+        record_driver_stat(&mut tx, driver_id, DriverStat::Rating(rating)).await?;
 
         tx.commit().await?;
 
@@ -269,23 +694,441 @@ impl TripAPI for Engine {
     }
 }
 
+impl Engine {
+    /// Computes the fare of a trip under way from its trace since pickup and the time elapsed,
+    /// bounded by the metering tolerance around the upfront fare. Returns `None` when there is
+    /// not enough of a trace to meter the trip.
+    async fn meter_fare(
+        &self,
+        tx: &mut Transaction<'_, Database>,
+        trip: &Trip,
+    ) -> Result<Option<MeteredFare>, Error> {
+        let (pickup_time, upfront) = match (trip.pickup_time(), &trip.fare_breakdown) {
+            (Some(pickup_time), Some(upfront)) => (pickup_time, upfront),
+            _ => return Err(invalid_invocation_error()),
+        };
+
+        let trace = fetch_trip_trace(&mut *tx, &trip.id, Some(pickup_time)).await?;
+
+        if trace.points.len() < 2 {
+            tracing::warn!("not enough of a trace to meter trip {}", trip.id);
+            return Ok(None);
+        }
+
+        let distance = trace.distance();
+        let duration = Utc::now() - pickup_time;
+        let tolerance = self.metering.tolerance;
+
+        let driver_id = trip.driver_id.ok_or_else(invalid_invocation_error)?;
+        let (min_fare, rate) = fetch_driver_rate(&mut *tx, &driver_id).await?;
+
+        let minutes = duration.num_seconds() as f64 / 60.0;
+
+        let metered = match self.pricing.tariff(&trip.route) {
+            Some(tariff) => tariff.metered(distance, minutes),
+            None => FareBreakdown::metered(
+                min_fare,
+                rate,
+                trip.pickup_distance,
+                distance,
+                minutes,
+                self.metering.per_minute,
+            ),
+        };
+
+        let breakdown = self
+            .adjust_fare(
+                metered.with_surge(trip.surge_multiplier),
+                trip.vehicle_class,
+                trip.is_pooled,
+            )
+            .bounded(
+                upfront.total * (1.0 - tolerance),
+                upfront.total * (1.0 + tolerance),
+            );
+
+        Ok(Some(MeteredFare {
+            distance,
+            duration: duration.num_seconds(),
+            fare: breakdown.total,
+            breakdown,
+        }))
+    }
+
+    /// Requests a driver for a trip if the driver is still available and their fare is within the
+    /// max fare of the trip, returning `None` otherwise.
+    pub(super) async fn offer_trip(
+        &self,
+        user: &User,
+        id: Uuid,
+        driver_id: Uuid,
+        distance: f64,
+    ) -> Result<Option<Trip>, Error> {
+        let mut conn = self.pool.acquire().await?;
+        let mut tx = conn.begin().await?;
+
+        let mut trip = fetch_trip_for_update(&mut tx, &id).await?;
+        let mut driver = fetch_driver_for_update(&mut tx, &driver_id).await?;
+
+        if !driver.is_available() {
+            return Ok(None);
+        }
+
+        let breakdown = match self
+            .driver_fare(&mut tx, &trip, driver_id, distance)
+            .await?
+        {
+            Some(breakdown) => breakdown,
+            None => return Ok(None),
+        };
+
+        tracing::info!("driver satisfies all conditions, attempting to update trip and driver...");
+
+        let from_status = trip.status.clone();
+        let fare = breakdown.total;
+
+        driver.request(trip.id)?;
+        trip.request_driver(driver_id, distance, breakdown)?;
+
+        update_driver(&mut tx, &driver).await?;
+        record_driver_stat(&mut tx, driver_id, DriverStat::Offer).await?;
+        update_trip(&mut tx, &trip).await?;
+        insert_trip_event(&mut tx, &user.id, Some(&from_status), &trip).await?;
+        insert_event(
+            &mut tx,
+            &DomainEvent::DriverRequested {
+                trip_id: trip.id,
+                driver_id,
+                fare,
+            },
+        )
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(Some(trip))
+    }
+
+    /// Returns the fare of a driver for a trip, or `None` if it exceeds the max fare of the trip
+    /// or the driver has already rejected the trip.
+    async fn driver_fare(
+        &self,
+        tx: &mut Transaction<'_, Database>,
+        trip: &Trip,
+        driver_id: Uuid,
+        pickup_distance: f64,
+    ) -> Result<Option<FareBreakdown>, Error> {
+        let (min_fare, rate): (Option<f64>, f64) = sqlx::query_as(
+            "SELECT min_fare::FLOAT8, rate::FLOAT8 FROM driver_rates WHERE driver_id = $1 FOR UPDATE",
+        )
+        .bind(driver_id)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        let breakdown = self.adjust_fare(
+            self.route_fare(&trip.route, min_fare.unwrap_or(0.0), rate, pickup_distance)
+                .with_surge(trip.surge_multiplier),
+            trip.vehicle_class,
+            trip.is_pooled,
+        );
+
+        if breakdown.total > trip.max_fare {
+            return Ok(None);
+        }
+
+        let maybe_trip_rejection = sqlx::query("SELECT driver_id FROM trip_rejections WHERE trip_id = $1 AND driver_id = $2 FOR UPDATE").bind(trip.id).bind(driver_id).fetch_optional(&mut *tx).await?;
+        if maybe_trip_rejection.is_some() {
+            return Ok(None);
+        }
+
+        Ok(Some(breakdown))
+    }
+
+    /// Offers a trip to up to `size` of the drivers at once, in the order they were found. The
+    /// first driver to accept is assigned and the offer is withdrawn from the others.
+    #[tracing::instrument(skip(self, drivers))]
+    async fn broadcast_trip(
+        &self,
+        user: &User,
+        id: Uuid,
+        drivers: Vec<(Uuid, f64)>,
+        size: usize,
+    ) -> Result<Option<Trip>, Error> {
+        let mut conn = self.pool.acquire().await?;
+        let mut tx = conn.begin().await?;
+
+        let mut trip = fetch_trip_for_update(&mut tx, &id).await?;
+
+        if !trip.is_searching() {
+            return Err(invalid_invocation_error());
+        }
+
+        let mut offers = vec![];
+
+        for (driver_id, distance) in drivers.into_iter() {
+            if offers.len() >= size {
+                break;
+            }
+
+            let mut driver = fetch_driver_for_update(&mut tx, &driver_id).await?;
+
+            if !driver.is_available() {
+                continue;
+            }
+
+            let breakdown = match self
+                .driver_fare(&mut tx, &trip, driver_id, distance)
+                .await?
+            {
+                Some(breakdown) => breakdown,
+                None => continue,
+            };
+
+            driver.request(trip.id)?;
+            update_driver(&mut tx, &driver).await?;
+            record_driver_stat(&mut tx, driver_id, DriverStat::Offer).await?;
+
+            offers.push(BroadcastOffer {
+                driver_id,
+                pickup_distance: distance,
+                fare: breakdown.total,
+                breakdown,
+            });
+        }
+
+        if offers.is_empty() {
+            tracing::warn!(
+                "failed to broadcast trip {} as no drivers satisfied all conditions",
+                id
+            );
+            return Ok(None);
+        }
+
+        let events: Vec<DomainEvent> = offers
+            .iter()
+            .map(|offer| DomainEvent::DriverRequested {
+                trip_id: trip.id,
+                driver_id: offer.driver_id,
+                fare: offer.fare,
+            })
+            .collect();
+
+        let from_status = trip.status.clone();
+
+        trip.broadcast(offers)?;
+
+        update_trip(&mut tx, &trip).await?;
+        insert_trip_event(&mut tx, &user.id, Some(&from_status), &trip).await?;
+
+        for event in events.iter() {
+            insert_event(&mut tx, event).await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(Some(trip))
+    }
+
+    /// Adds a pooled trip to the pool of a nearby driver, inserting its pickup and dropoff into
+    /// the stops of the driver where the detour limits hold, preferring the insertion that adds
+    /// the least distance. Drivers serving a pool are assigned the trip directly.
+    #[tracing::instrument(skip(self))]
+    async fn join_pool(&self, user: &User, trip: &Trip) -> Result<Option<Trip>, Error> {
+        let origin_location: Geometry<f64> = trip.route.origin.coordinates.clone().into();
+
+        // locations are stored as (lat, lng) points, so they are flipped to measure distances in
+        // meters
+        let query = "
+            SELECT
+                d.data,
+                (v.data->>'capacity')::INT4 AS capacity,
+                ST_X(l.location) AS lat,
+                ST_Y(l.location) AS lng
+            FROM
+                drivers d
+                JOIN driver_locations l ON d.id = l.driver_id
+                LEFT JOIN vehicles v ON d.id = v.driver_id
+            WHERE
+                d.status = 'pooled'
+                AND COALESCE(v.class, 'economy') = $4
+                AND l.expiry > now()
+                AND ST_DWithin(
+                    ST_FlipCoordinates(l.location)::geography,
+                    ST_FlipCoordinates(ST_SetSRID($1, 4326))::geography,
+                    $2
+                )
+                AND ($3::TIMESTAMPTZ IS NULL OR NOT EXISTS (
+                    SELECT 1 FROM driver_location_flags f WHERE f.driver_id = d.id AND f.timestamp > $3
+                ))
+        ";
+
+        let mut conn = self.pool.acquire().await?;
+
+        let results = conn
+            .fetch_all(
+                sqlx::query(query)
+                    .bind(wkb::Encode(origin_location))
+                    .bind(self.pooling.search_radius)
+                    .bind(self.spoofing.withhold_since(Utc::now()))
+                    .bind(trip.vehicle_class.name()),
+            )
+            .await?;
+
+        let (pickup, dropoff) = trip.pool_stops();
+        let mut candidates = vec![];
+
+        for result in results.iter() {
+            let Json(driver): Json<Driver> = result.try_get("data")?;
+            let capacity: Option<i32> = result.try_get("capacity")?;
+            let position = Coordinates {
+                lat: result.try_get("lat")?,
+                lng: result.try_get("lng")?,
+            };
+
+            // drivers without a registered vehicle are only limited by the pool size
+            let capacity = capacity.map_or(usize::MAX, |capacity| capacity.max(0) as usize);
+
+            if let DriverStatus::Pooled { stops, .. } = &driver.status {
+                if let Some((_, added)) =
+                    self.pooling
+                        .insert(&position, stops, pickup.clone(), dropoff.clone(), capacity)
+                {
+                    candidates.push((driver.id, position, capacity, added));
+                }
+            }
+        }
+
+        candidates.sort_by(|a, b| a.3.total_cmp(&b.3));
+
+        for (driver_id, position, capacity, _) in candidates.into_iter() {
+            let mut tx = conn.begin().await?;
+
+            let mut trip = fetch_trip_for_update(&mut tx, &trip.id).await?;
+            let mut driver = fetch_driver_for_update(&mut tx, &driver_id).await?;
+
+            // the stops of the driver may have changed since the pool was found
+            let stops = match &driver.status {
+                DriverStatus::Pooled { stops, .. } => {
+                    match self.pooling.insert(
+                        &position,
+                        stops,
+                        pickup.clone(),
+                        dropoff.clone(),
+                        capacity,
+                    ) {
+                        Some((stops, _)) => stops,
+                        None => continue,
+                    }
+                }
+                _ => continue,
+            };
+
+            let (min_fare, rate): (Option<f64>, f64) = sqlx::query_as(
+                "SELECT min_fare::FLOAT8, rate::FLOAT8 FROM driver_rates WHERE driver_id = $1 FOR UPDATE",
+            )
+            .bind(driver.id)
+            .fetch_one(&mut tx)
+            .await?;
+
+            // the driver is already on the way, so the pickup distance is not charged
+            let breakdown = self.adjust_fare(
+                self.route_fare(&trip.route, min_fare.unwrap_or(0.0), rate, 0.0)
+                    .with_surge(trip.surge_multiplier),
+                trip.vehicle_class,
+                true,
+            );
+
+            if breakdown.total > trip.max_fare {
+                continue;
+            }
+
+            let from_status = trip.status.clone();
+            let fare = breakdown.total;
+
+            trip.request_driver(driver_id, 0.0, breakdown)?;
+            trip.assign_driver()?;
+            driver.join_pool(trip.id, stops.clone())?;
+
+            update_driver(&mut tx, &driver).await?;
+            update_trip(&mut tx, &trip).await?;
+            insert_trip_event(&mut tx, &user.id, Some(&from_status), &trip).await?;
+            insert_event(
+                &mut tx,
+                &DomainEvent::TripPooled {
+                    trip_id: trip.id,
+                    driver_id,
+                    fare,
+                    stops,
+                },
+            )
+            .await?;
+
+            tx.commit().await?;
+
+            return Ok(Some(trip));
+        }
+
+        Ok(None)
+    }
+
+    /// Requests drivers for trips whose passengers chose to wait for a driver to appear, such as
+    /// trips booked from estimated quotes.
+    #[tracing::instrument(skip(self))]
+    pub async fn dispatch_waiting_trips(&self) -> Result<(), Error> {
+        let user = User::new_system_user();
+
+        let trip_ids: Vec<Uuid> = sqlx::query_scalar(
+            "SELECT id FROM trips WHERE status = 'searching' AND (data->>'wait_for_driver')::BOOLEAN",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        for trip_id in trip_ids.into_iter() {
+            match self.request_driver(user.clone(), trip_id).await {
+                Ok(Some(_)) => tracing::info!("requested driver for waiting trip {}", trip_id),
+                Ok(None) => tracing::info!("no drivers found for waiting trip {}", trip_id),
+                Err(err) => {
+                    tracing::warn!("failed to request driver for trip {}: {:?}", trip_id, err)
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
 async fn release_driver(
     tx: &mut Transaction<'_, Database>,
+    user: &User,
     trip: &mut Trip,
     driver_id: Uuid,
     rejection: bool,
 ) -> Result<(), Error> {
-    if trip.release_driver()? != driver_id {
-        return Err(invalid_invocation_error());
-    }
+    let from_status = trip.status.clone();
+
+    trip.release_driver(driver_id)?;
 
     let mut driver = fetch_driver_for_update(tx, &driver_id).await?;
 
     update_trip(tx, &trip).await?;
+    insert_trip_event(tx, &user.id, Some(&from_status), trip).await?;
     driver.free()?;
 
     update_driver(tx, &driver).await?;
 
+    let event = match rejection {
+        true => DomainEvent::TripRejected {
+            trip_id: trip.id,
+            driver_id,
+        },
+        false => DomainEvent::DriverReleased {
+            trip_id: trip.id,
+            driver_id,
+        },
+    };
+
+    insert_event(tx, &event).await?;
+
     if rejection {
         tx.execute(
             sqlx::query("INSERT INTO trip_rejections (trip_id, driver_id) VALUES ($1, $2)")
@@ -293,11 +1136,15 @@ async fn release_driver(
                 .bind(&driver.id),
         )
         .await?;
-
-        tx.execute(sqlx::query("UPDATE driver_priorities SET priority = GREATEST(0, priority - 1) WHERE driver_id = $1").bind(&driver.id)).await?;
-    } else {
-        tx.execute(sqlx::query("UPDATE driver_priorities SET priority = LEAST(1, priority + 1) WHERE driver_id = $1").bind(&driver.id)).await?;
     }
 
+    // drivers are released by the system once their offer lapses
+    let stat = match rejection {
+        true => DriverStat::Rejection,
+        false => DriverStat::Expiration,
+    };
+
+    record_driver_stat(tx, driver_id, stat).await?;
+
     Ok(())
 }
@@ -2,6 +2,7 @@ use oso::PolarClass;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+use crate::entities::Coordinates;
 use crate::error::{invalid_invocation_error, Error};
 
 #[derive(Clone, Debug, Serialize, Deserialize, PolarClass)]
@@ -17,8 +18,32 @@ pub struct Driver {
 pub enum Status {
     Inactive,
     Available,
-    Requested { trip_id: Uuid },
-    Assigned { trip_id: Uuid },
+    Requested {
+        trip_id: Uuid,
+    },
+    Assigned {
+        trip_id: Uuid,
+    },
+    // serving one or more pooled trips, visiting the remaining stops in order
+    Pooled {
+        trip_ids: Vec<Uuid>,
+        stops: Vec<PoolStop>,
+    },
+}
+
+/// A pickup or dropoff of one of the pooled trips served by a driver.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PoolStop {
+    pub trip_id: Uuid,
+    pub kind: PoolStopKind,
+    pub coordinates: Coordinates,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PoolStopKind {
+    Pickup,
+    Dropoff,
 }
 
 impl Status {
@@ -28,6 +53,7 @@ impl Status {
             Self::Available => "available".into(),
             Self::Requested { trip_id: _ } => "requested".into(),
             Self::Assigned { trip_id: _ } => "assigned".into(),
+            Self::Pooled { .. } => "pooled".into(),
         }
     }
 }
@@ -87,6 +113,71 @@ impl Driver {
         }
     }
 
+    /// Assigns the requested trip as the first of a pool, to be served by visiting the given
+    /// stops.
+    #[tracing::instrument]
+    pub fn assign_pooled(&mut self, stops: Vec<PoolStop>) -> Result<(), Error> {
+        match self.status {
+            Status::Requested { trip_id } => {
+                self.status = Status::Pooled {
+                    trip_ids: vec![trip_id],
+                    stops,
+                };
+                Ok(())
+            }
+            _ => Err(invalid_invocation_error()),
+        }
+    }
+
+    /// Adds a trip to the pool of the driver, replacing the stops still to be visited.
+    #[tracing::instrument]
+    pub fn join_pool(&mut self, trip_id: Uuid, stops: Vec<PoolStop>) -> Result<(), Error> {
+        match &mut self.status {
+            Status::Pooled {
+                trip_ids,
+                stops: remaining,
+            } => {
+                trip_ids.push(trip_id);
+                *remaining = stops;
+                Ok(())
+            }
+            _ => Err(invalid_invocation_error()),
+        }
+    }
+
+    /// Marks the pickup or dropoff of a pooled trip as visited. Drivers serving a single trip
+    /// have no stops to update.
+    #[tracing::instrument]
+    pub fn visit_stop(&mut self, trip_id: &Uuid, kind: PoolStopKind) {
+        if let Status::Pooled { stops, .. } = &mut self.status {
+            stops.retain(|stop| !(stop.trip_id == *trip_id && stop.kind == kind));
+        }
+    }
+
+    /// Releases the driver from a trip once it is completed or cancelled, freeing the driver
+    /// when no other pooled trips remain.
+    #[tracing::instrument]
+    pub fn release_trip(&mut self, trip_id: &Uuid) -> Result<(), Error> {
+        match &mut self.status {
+            Status::Requested { trip_id: id } | Status::Assigned { trip_id: id }
+                if id == trip_id =>
+            {
+                self.status = Status::Available;
+                Ok(())
+            }
+            Status::Pooled { trip_ids, stops } if trip_ids.contains(trip_id) => {
+                trip_ids.retain(|id| id != trip_id);
+                stops.retain(|stop| stop.trip_id != *trip_id);
+
+                if trip_ids.is_empty() {
+                    self.status = Status::Available;
+                }
+                Ok(())
+            }
+            _ => Err(invalid_invocation_error()),
+        }
+    }
+
     #[tracing::instrument]
     pub fn free(&mut self) -> Result<(), Error> {
         match self.status {
@@ -0,0 +1,22 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::entities::Coordinates;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DriverLocation {
+    pub driver_id: Uuid,
+    pub coordinates: Coordinates,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// A location reported by a driver's device at the time it was recorded.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LocationSample {
+    pub coordinates: Coordinates,
+    pub timestamp: DateTime<Utc>,
+    // whether the device reported the location as coming from a mock provider
+    #[serde(default)]
+    pub is_mock: bool,
+}
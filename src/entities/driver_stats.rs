@@ -0,0 +1,22 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// How a driver responded to the trips offered to them over the last `window` days, counted as
+/// they happen.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct DriverStats {
+    pub driver_id: Uuid,
+    pub window: i32,
+    pub offers: i32,
+    pub acceptances: i32,
+    pub rejections: i32,
+    // offers the driver let lapse without responding
+    pub expirations: i32,
+    pub cancellations: i32,
+    pub arrivals: i32,
+    pub late_arrivals: i32,
+    pub ratings: i32,
+    pub rating_total: f64,
+    pub last_trip_at: Option<DateTime<Utc>>,
+}
@@ -0,0 +1,137 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::entities::{PenaltyBearer, PoolStop, SafetyAlert};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum DomainEvent {
+    TripCreated {
+        trip_id: Uuid,
+        passenger_id: Uuid,
+    },
+    DriverRequested {
+        trip_id: Uuid,
+        driver_id: Uuid,
+        fare: f64,
+    },
+    DriverCommitted {
+        trip_id: Uuid,
+        driver_id: Uuid,
+    },
+    DriverWithdrew {
+        trip_id: Uuid,
+        driver_id: Uuid,
+        // whether the driver withdrew too close to pickup to avoid a penalty
+        is_late: bool,
+    },
+    TripDispatched {
+        trip_id: Uuid,
+    },
+    DriverReleased {
+        trip_id: Uuid,
+        driver_id: Uuid,
+    },
+    TripAccepted {
+        trip_id: Uuid,
+        driver_id: Uuid,
+    },
+    // a pooled trip assigned to a driver already serving a pool, with the driver's new stops
+    TripPooled {
+        trip_id: Uuid,
+        driver_id: Uuid,
+        fare: f64,
+        stops: Vec<PoolStop>,
+    },
+    TripRejected {
+        trip_id: Uuid,
+        driver_id: Uuid,
+    },
+    // the offer of a broadcast trip withdrawn once another driver accepted it
+    OfferWithdrawn {
+        trip_id: Uuid,
+        driver_id: Uuid,
+    },
+    TripCancelled {
+        trip_id: Uuid,
+        cancelled_by: Uuid,
+        penalty_bearer: Option<PenaltyBearer>,
+    },
+    OriginArrived {
+        trip_id: Uuid,
+    },
+    StopArrived {
+        trip_id: Uuid,
+        stop: usize,
+    },
+    TripCompleted {
+        trip_id: Uuid,
+    },
+    TripRated {
+        trip_id: Uuid,
+        driver_id: Uuid,
+        rating: i32,
+    },
+    RouteChangeRequested {
+        trip_id: Uuid,
+        previous_fare: f64,
+        fare: f64,
+    },
+    RouteChangeAcknowledged {
+        trip_id: Uuid,
+        driver_id: Uuid,
+    },
+    SafetyAlertRaised {
+        trip_id: Uuid,
+        alert: SafetyAlert,
+    },
+    DriverStarted {
+        driver_id: Uuid,
+    },
+    DriverStopped {
+        driver_id: Uuid,
+    },
+    // stopped by the platform as the driver's location went stale
+    DriverDeactivated {
+        driver_id: Uuid,
+    },
+}
+
+impl DomainEvent {
+    /// Returns the id of the trip the event relates to, if any.
+    pub fn trip_id(&self) -> Option<Uuid> {
+        match self {
+            Self::TripCreated { trip_id, .. }
+            | Self::DriverRequested { trip_id, .. }
+            | Self::DriverCommitted { trip_id, .. }
+            | Self::DriverWithdrew { trip_id, .. }
+            | Self::TripDispatched { trip_id }
+            | Self::DriverReleased { trip_id, .. }
+            | Self::TripAccepted { trip_id, .. }
+            | Self::TripPooled { trip_id, .. }
+            | Self::TripRejected { trip_id, .. }
+            | Self::OfferWithdrawn { trip_id, .. }
+            | Self::TripCancelled { trip_id, .. }
+            | Self::OriginArrived { trip_id }
+            | Self::StopArrived { trip_id, .. }
+            | Self::TripCompleted { trip_id }
+            | Self::TripRated { trip_id, .. }
+            | Self::RouteChangeRequested { trip_id, .. }
+            | Self::RouteChangeAcknowledged { trip_id, .. }
+            | Self::SafetyAlertRaised { trip_id, .. } => Some(*trip_id),
+            Self::DriverStarted { .. }
+            | Self::DriverStopped { .. }
+            | Self::DriverDeactivated { .. } => None,
+        }
+    }
+}
+
+/// A domain event as stored in the outbox, published once the transaction that produced it has
+/// committed.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct OutboxEvent {
+    pub id: i64,
+    pub created_at: DateTime<Utc>,
+    pub event: DomainEvent,
+}
@@ -0,0 +1,297 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FareBreakdown {
+    pub items: Vec<LineItem>,
+    pub surge_multiplier: f64,
+    pub total: f64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LineItem {
+    pub kind: LineItemKind,
+    pub amount: f64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LineItemKind {
+    Base,
+    MinFare,
+    Distance,
+    Time,
+    PickupDistance,
+    Surge,
+    Fee,
+    Tax,
+    Discount,
+    Adjustment,
+}
+
+/// Platform-wide amounts applied on top of a driver's fare.
+#[derive(Clone, Debug, Default)]
+pub struct FareAdjustments {
+    pub booking_fee: f64,
+    pub tax_rate: f64,
+    pub discount: f64,
+}
+
+impl FareBreakdown {
+    /// Builds the breakdown of a driver's fare, where either the driver's minimum fare applies or
+    /// the rate is charged over the pickup distance and the route distance.
+    pub fn from_rate(min_fare: f64, rate: f64, pickup_distance: f64, route_distance: f64) -> Self {
+        let distance_fare = rate * route_distance;
+        let pickup_fare = rate * pickup_distance;
+
+        if min_fare >= distance_fare + pickup_fare {
+            return Self::from_items(vec![LineItem::new(LineItemKind::MinFare, min_fare)]);
+        }
+
+        Self::from_items(vec![
+            LineItem::new(LineItemKind::Distance, distance_fare),
+            LineItem::new(LineItemKind::PickupDistance, pickup_fare),
+        ])
+    }
+
+    /// Builds a breakdown consisting of a single amount charged as the minimum fare.
+    pub fn fixed(amount: f64) -> Self {
+        Self::from_items(vec![LineItem::new(LineItemKind::MinFare, amount)])
+    }
+
+    pub fn from_items(items: Vec<LineItem>) -> Self {
+        let mut breakdown = Self {
+            items,
+            surge_multiplier: 1.0,
+            total: 0.0,
+        };
+
+        breakdown.update_total();
+        breakdown
+    }
+
+    /// Linearly interpolates between two breakdowns item by item, so that the result still sums
+    /// to its total. A `t` of 0.0 yields `a` and a `t` of 1.0 yields `b`.
+    pub fn interpolate(a: &Self, b: &Self, t: f64) -> Self {
+        let mut items: Vec<LineItem> = vec![];
+
+        for item in a.items.iter().chain(b.items.iter()) {
+            if items.iter().any(|x| x.kind == item.kind) {
+                continue;
+            }
+
+            let amount = a.amount(item.kind) * (1.0 - t) + b.amount(item.kind) * t;
+            items.push(LineItem::new(item.kind, amount));
+        }
+
+        let mut breakdown = Self::from_items(items);
+        breakdown.surge_multiplier = a.surge_multiplier * (1.0 - t) + b.surge_multiplier * t;
+        breakdown
+    }
+
+    /// Returns the sum of all line items of the given kind.
+    pub fn amount(&self, kind: LineItemKind) -> f64 {
+        self.items
+            .iter()
+            .filter(|item| item.kind == kind)
+            .map(|item| item.amount)
+            .sum()
+    }
+
+    /// Returns the portion of the total that is paid out to the driver, before any surge, fees,
+    /// taxes or discounts.
+    pub fn driver_fare(&self) -> f64 {
+        self.amount(LineItemKind::Base)
+            + self.amount(LineItemKind::MinFare)
+            + self.amount(LineItemKind::Distance)
+            + self.amount(LineItemKind::Time)
+            + self.amount(LineItemKind::PickupDistance)
+    }
+
+    /// Scales every line item by the given factor, such as the multiplier of a vehicle class.
+    pub fn scaled(mut self, factor: f64) -> Self {
+        for item in self.items.iter_mut() {
+            item.amount *= factor;
+        }
+
+        self.update_total();
+        self
+    }
+
+    pub fn with_surge(mut self, multiplier: f64) -> Self {
+        self.surge_multiplier = multiplier;
+
+        if multiplier != 1.0 {
+            let amount = self.driver_fare() * (multiplier - 1.0);
+            self.push(LineItem::new(LineItemKind::Surge, amount));
+        }
+
+        self
+    }
+
+    pub fn with_adjustments(mut self, adjustments: &FareAdjustments) -> Self {
+        if adjustments.booking_fee > 0.0 {
+            self.push(LineItem::new(LineItemKind::Fee, adjustments.booking_fee));
+        }
+
+        if adjustments.discount > 0.0 {
+            let amount = f64::min(adjustments.discount, self.total);
+            self.push(LineItem::new(LineItemKind::Discount, -amount));
+        }
+
+        if adjustments.tax_rate > 0.0 {
+            let amount = self.total * adjustments.tax_rate;
+            self.push(LineItem::new(LineItemKind::Tax, amount));
+        }
+
+        self
+    }
+
+    /// Builds the breakdown of a driver's fare from the distance actually travelled and the time
+    /// actually taken, where the minimum fare still applies if the distance fare falls short of
+    /// it, as in `from_rate`.
+    pub fn metered(
+        min_fare: f64,
+        rate: f64,
+        pickup_distance: f64,
+        distance: f64,
+        minutes: f64,
+        per_minute: f64,
+    ) -> Self {
+        let mut breakdown = Self::from_rate(min_fare, rate, pickup_distance, distance);
+
+        if per_minute > 0.0 {
+            breakdown.push(LineItem::new(LineItemKind::Time, per_minute * minutes));
+        }
+
+        breakdown
+    }
+
+    /// Brings the total within the given bounds by adding an adjustment line item.
+    pub fn bounded(mut self, min: f64, max: f64) -> Self {
+        let total = self.total.clamp(min, max);
+
+        if total != self.total {
+            self.push(LineItem::new(LineItemKind::Adjustment, total - self.total));
+        }
+
+        self
+    }
+
+    fn push(&mut self, item: LineItem) {
+        self.items.push(item);
+        self.update_total();
+    }
+
+    fn update_total(&mut self) {
+        self.total = self.items.iter().map(|item| item.amount).sum();
+    }
+}
+
+impl LineItem {
+    pub fn new(kind: LineItemKind, amount: f64) -> Self {
+        Self { kind, amount }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sum(breakdown: &FareBreakdown) -> f64 {
+        breakdown.items.iter().map(|item| item.amount).sum()
+    }
+
+    #[test]
+    fn min_fare_test() {
+        let breakdown = FareBreakdown::from_rate(20.0, 0.001, 1000.0, 4000.0);
+
+        assert_eq!(breakdown.items.len(), 1);
+        assert_eq!(breakdown.amount(LineItemKind::MinFare), 20.0);
+        assert_eq!(breakdown.total, 20.0);
+    }
+
+    #[test]
+    fn distance_fare_test() {
+        let breakdown = FareBreakdown::from_rate(1.0, 0.01, 1000.0, 4000.0);
+
+        assert_eq!(breakdown.amount(LineItemKind::MinFare), 0.0);
+        assert_eq!(breakdown.amount(LineItemKind::Distance), 40.0);
+        assert_eq!(breakdown.amount(LineItemKind::PickupDistance), 10.0);
+        assert_eq!(breakdown.total, 50.0);
+    }
+
+    #[test]
+    fn adjustments_sum_to_total_test() {
+        let adjustments = FareAdjustments {
+            booking_fee: 1.5,
+            tax_rate: 0.08,
+            discount: 3.0,
+        };
+
+        let breakdown = FareBreakdown::from_rate(1.0, 0.0137, 731.0, 4213.0)
+            .with_surge(1.3)
+            .with_adjustments(&adjustments);
+
+        assert_eq!(breakdown.surge_multiplier, 1.3);
+        assert_eq!(breakdown.amount(LineItemKind::Fee), 1.5);
+        assert_eq!(breakdown.amount(LineItemKind::Discount), -3.0);
+        assert!(breakdown.amount(LineItemKind::Tax) > 0.0);
+        assert_eq!(breakdown.total, sum(&breakdown));
+    }
+
+    #[test]
+    fn metered_test() {
+        let metered = FareBreakdown::metered(1.0, 0.01, 1000.0, 5000.0, 20.0, 0.5).with_surge(1.5);
+
+        assert_eq!(metered.amount(LineItemKind::Distance), 50.0);
+        assert_eq!(metered.amount(LineItemKind::PickupDistance), 10.0);
+        assert_eq!(metered.amount(LineItemKind::Time), 10.0);
+        assert_eq!(metered.amount(LineItemKind::Surge), 35.0);
+        assert_eq!(metered.total, 105.0);
+
+        let bounded = metered.clone().bounded(0.0, 90.0);
+        assert_eq!(bounded.amount(LineItemKind::Adjustment), -15.0);
+        assert_eq!(bounded.total, 90.0);
+        assert_eq!(bounded.total, sum(&bounded));
+
+        let bounded = metered.clone().bounded(0.0, 1000.0);
+        assert_eq!(bounded, metered);
+
+        // the distance fare takes over from the minimum fare once the route is long enough
+        let short = FareBreakdown::metered(20.0, 0.001, 0.0, 4000.0, 0.0, 0.0);
+        assert_eq!(short.amount(LineItemKind::MinFare), 20.0);
+        assert_eq!(short.total, 20.0);
+
+        let long = FareBreakdown::metered(20.0, 0.001, 0.0, 40000.0, 0.0, 0.0);
+        assert_eq!(long.amount(LineItemKind::MinFare), 0.0);
+        assert_eq!(long.amount(LineItemKind::Distance), 40.0);
+        assert_eq!(long.total, 40.0);
+    }
+
+    #[test]
+    fn scaled_test() {
+        let breakdown = FareBreakdown::from_rate(1.0, 0.01, 1000.0, 4000.0)
+            .with_surge(1.5)
+            .scaled(1.2);
+
+        assert_eq!(breakdown.amount(LineItemKind::Distance), 48.0);
+        assert_eq!(breakdown.amount(LineItemKind::PickupDistance), 12.0);
+        assert_eq!(breakdown.amount(LineItemKind::Surge), 30.0);
+        assert_eq!(breakdown.total, 90.0);
+        assert_eq!(breakdown.surge_multiplier, 1.5);
+    }
+
+    #[test]
+    fn interpolate_test() {
+        let a = FareBreakdown::fixed(20.0);
+        let b = FareBreakdown::from_rate(1.0, 0.01, 1000.0, 4000.0);
+
+        let median = FareBreakdown::interpolate(&a, &b, 0.5);
+
+        assert_eq!(median.amount(LineItemKind::MinFare), 10.0);
+        assert_eq!(median.amount(LineItemKind::Distance), 20.0);
+        assert_eq!(median.amount(LineItemKind::PickupDistance), 5.0);
+        assert_eq!(median.total, 35.0);
+        assert_eq!(median.total, sum(&median));
+    }
+}
@@ -31,12 +31,32 @@ impl Location {
     }
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Coordinates {
     pub lat: f64,
     pub lng: f64,
 }
 
+impl Coordinates {
+    /// Returns the great-circle distance to other coordinates, in meters.
+    pub fn distance(&self, other: &Coordinates) -> f64 {
+        const EARTH_RADIUS: f64 = 6_371_000.0;
+
+        let (lat1, lat2) = (self.lat.to_radians(), other.lat.to_radians());
+        let d_lat = lat2 - lat1;
+        let d_lng = (other.lng - self.lng).to_radians();
+
+        let a = (d_lat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (d_lng / 2.0).sin().powi(2);
+
+        2.0 * EARTH_RADIUS * a.sqrt().asin()
+    }
+
+    /// Checks that the coordinates are within the range of latitudes and longitudes.
+    pub fn is_valid(&self) -> bool {
+        (-90.0..=90.0).contains(&self.lat) && (-180.0..=180.0).contains(&self.lng)
+    }
+}
+
 impl Into<String> for Coordinates {
     fn into(self) -> String {
         format!("{}, {}", self.lat, self.lng)
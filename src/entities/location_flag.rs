@@ -0,0 +1,26 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::entities::Coordinates;
+
+/// A location reported by a driver that suggests their device is spoofing its position.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LocationFlag {
+    pub driver_id: Uuid,
+    pub kind: LocationFlagKind,
+    pub coordinates: Coordinates,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// The reason a location was flagged, with speeds in meters per second.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "name", rename_all = "snake_case")]
+pub enum LocationFlagKind {
+    // the driver would have had to travel implausibly fast since their last location
+    ImplausibleSpeed { speed: f64 },
+    // the same point was reported over and over, which real receivers hardly ever do
+    RepeatedPoint { count: usize },
+    // the device reported the location as coming from a mock provider
+    MockLocation,
+}
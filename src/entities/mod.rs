@@ -1,13 +1,39 @@
 mod driver;
+mod driver_location;
+mod driver_stats;
+mod event;
+mod fare;
 mod location;
+mod location_flag;
+mod offer;
 mod passenger;
 mod quote;
 mod route;
+mod safety;
+mod surge;
+mod trace;
 mod trip;
+mod trip_event;
+mod trip_update;
+mod vehicle;
 
-pub use driver::{Driver, Status as DriverStatus};
+pub use driver::{Driver, PoolStop, PoolStopKind, Status as DriverStatus};
+pub use driver_location::{DriverLocation, LocationSample};
+pub use driver_stats::DriverStats;
+pub use event::{DomainEvent, OutboxEvent};
+pub use fare::{FareAdjustments, FareBreakdown, LineItem, LineItemKind};
 pub use location::{Coordinates, Location, LocationSource};
+pub use location_flag::{LocationFlag, LocationFlagKind};
+pub use offer::{DriverOffer, OfferUpdate, ScheduledTripListing};
 pub use passenger::Passenger;
 pub use quote::Quote;
 pub use route::Route;
-pub use trip::{Status as TripStatus, Trip};
+pub use safety::{SafetyAlert, SafetyAlertKind};
+pub use surge::SurgeCell;
+pub use trace::{TracePoint, TripTrace};
+pub use trip::{
+    BroadcastOffer, MeteredFare, PenaltyBearer, RouteChange, Status as TripStatus, Trip,
+};
+pub use trip_event::TripEvent;
+pub use trip_update::TripUpdate;
+pub use vehicle::{AccessibilityFeature, Vehicle, VehicleClass};
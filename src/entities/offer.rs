@@ -0,0 +1,42 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::entities::{FareBreakdown, Location, PoolStop, VehicleClass};
+
+/// A trip offered to a driver, revealing only the area of the destination until the trip is
+/// accepted.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DriverOffer {
+    pub trip_id: Uuid,
+    pub pickup: Location,
+    pub pickup_distance: f64,
+    pub fare: f64,
+    pub breakdown: FareBreakdown,
+    // geohash of the cell containing the destination
+    pub destination_area: String,
+    pub deadline: DateTime<Utc>,
+}
+
+/// A scheduled trip listed to drivers who may commit to it, revealing only the area of the pickup
+/// until a driver commits.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ScheduledTripListing {
+    pub trip_id: Uuid,
+    // geohash of the cell containing the pickup
+    pub pickup_area: String,
+    pub pickup_at: DateTime<Utc>,
+    pub fare: f64,
+    pub vehicle_class: VehicleClass,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum OfferUpdate {
+    Offer { offer: DriverOffer },
+    Withdrawn { trip_id: Uuid },
+    // a trip joined the pool of the driver, changing the stops to visit
+    Pooled { trip_id: Uuid, stops: Vec<PoolStop> },
+    // the driver was stopped as their location went stale and must start again to get offers
+    Deactivated,
+}
@@ -42,6 +42,10 @@ impl Passenger {
         }
     }
 
+    pub fn is_active_in(&self, trip_id: &Uuid) -> bool {
+        matches!(&self.status, Status::Active { trip_id: id } if id == trip_id)
+    }
+
     pub fn activate(&mut self, trip_id: Uuid) -> Result<(), Error> {
         match self.status {
             Status::Inactive => {
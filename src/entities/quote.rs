@@ -1,21 +1,82 @@
+use chrono::{DateTime, Duration, Utc};
+use oso::PolarClass;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-use crate::entities::Route;
+use crate::entities::{FareBreakdown, Route, VehicleClass};
+use crate::error::{expired_error, invalid_invocation_error, Error};
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, PolarClass)]
 pub struct Quote {
+    #[polar(attribute)]
     pub token: Uuid,
+    #[polar(attribute)]
+    pub passenger_id: Uuid,
     pub route: Route,
     pub max_fare: f64,
+    pub breakdown: FareBreakdown,
+    // estimates are derived from past trips when no drivers are nearby
+    pub is_estimate: bool,
+    pub expires_at: DateTime<Utc>,
+    // the pickup time of trips booked in advance
+    pub pickup_at: Option<DateTime<Utc>>,
+    // pooled trips may share the vehicle with other passengers at a discount
+    pub is_pooled: bool,
+    pub vehicle_class: VehicleClass,
+    // the trip created from the quote, quotes may only be used once
+    pub trip_id: Option<Uuid>,
 }
 
 impl Quote {
-    pub fn new(route: Route, max_fare: f64) -> Self {
+    pub fn new(passenger_id: Uuid, route: Route, breakdown: FareBreakdown, ttl: Duration) -> Self {
         Self {
             token: Uuid::new_v4(),
+            passenger_id,
             route,
-            max_fare,
+            max_fare: breakdown.total,
+            breakdown,
+            is_estimate: false,
+            expires_at: Utc::now() + ttl,
+            pickup_at: None,
+            is_pooled: false,
+            vehicle_class: VehicleClass::default(),
+            trip_id: None,
         }
     }
+
+    pub fn new_estimate(
+        passenger_id: Uuid,
+        route: Route,
+        breakdown: FareBreakdown,
+        ttl: Duration,
+    ) -> Self {
+        Self {
+            is_estimate: true,
+            ..Self::new(passenger_id, route, breakdown, ttl)
+        }
+    }
+
+    pub fn is_expired(&self) -> bool {
+        Utc::now() >= self.expires_at
+    }
+
+    pub fn ensure_unexpired(&self) -> Result<(), Error> {
+        if self.is_expired() {
+            return Err(expired_error());
+        }
+
+        Ok(())
+    }
+
+    #[tracing::instrument]
+    pub fn consume(&mut self, trip_id: Uuid) -> Result<(), Error> {
+        self.ensure_unexpired()?;
+
+        if self.trip_id.is_some() {
+            return Err(invalid_invocation_error());
+        }
+
+        self.trip_id = Some(trip_id);
+        Ok(())
+    }
 }
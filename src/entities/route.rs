@@ -2,12 +2,15 @@ use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use uuid::Uuid;
 
-use crate::entities::Location;
+use crate::entities::{Coordinates, Location};
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Route {
     pub token: Uuid,
     pub origin: Location,
+    // intermediate stops, in the order they are visited
+    #[serde(default)]
+    pub stops: Vec<Location>,
     pub destination: Location,
     pub directions: Value,
     pub distance: f64,
@@ -19,9 +22,50 @@ impl Route {
         Route {
             token: Uuid::new_v4(),
             origin,
+            stops: vec![],
             destination,
             directions,
             distance,
         }
     }
+
+    pub fn with_stops(mut self, stops: Vec<Location>) -> Self {
+        self.stops = stops;
+        self
+    }
+
+    /// Returns the origin, the stops and the destination of the route in order.
+    pub fn waypoints(&self) -> Vec<&Location> {
+        std::iter::once(&self.origin)
+            .chain(self.stops.iter())
+            .chain(std::iter::once(&self.destination))
+            .collect()
+    }
+
+    /// Returns the planned path of the route from the GeoJSON LineString of its directions, either
+    /// given directly or as the geometry of the directions. Returns `None` for routes without
+    /// directions, as straight lines between the waypoints say little about the roads taken.
+    pub fn path(&self) -> Option<Vec<Coordinates>> {
+        let geometry = match self.directions.get("geometry") {
+            Some(geometry) => geometry,
+            None => &self.directions,
+        };
+
+        let path: Option<Vec<Coordinates>> = geometry
+            .get("coordinates")
+            .and_then(Value::as_array)
+            .and_then(|positions| {
+                positions
+                    .iter()
+                    .map(|position| {
+                        Some(Coordinates {
+                            lat: position.get(1)?.as_f64()?,
+                            lng: position.get(0)?.as_f64()?,
+                        })
+                    })
+                    .collect()
+            });
+
+        path.filter(|path| path.len() >= 2)
+    }
 }
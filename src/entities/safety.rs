@@ -0,0 +1,60 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::entities::Coordinates;
+
+/// An alert raised by the safety monitor for a trip under way.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SafetyAlert {
+    pub id: Uuid,
+    pub trip_id: Uuid,
+    pub driver_id: Uuid,
+    pub kind: SafetyAlertKind,
+    pub coordinates: Coordinates,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// The reason for an alert, with distances in meters and durations in seconds.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "name", rename_all = "snake_case")]
+pub enum SafetyAlertKind {
+    RouteDeviation {
+        distance: f64,
+    },
+    UnexpectedStop {
+        duration: i64,
+    },
+    Overdue {
+        expected_duration: i64,
+        duration: i64,
+    },
+}
+
+impl SafetyAlertKind {
+    pub fn name(&self) -> String {
+        match self {
+            Self::RouteDeviation { .. } => "route_deviation".into(),
+            Self::UnexpectedStop { .. } => "unexpected_stop".into(),
+            Self::Overdue { .. } => "overdue".into(),
+        }
+    }
+}
+
+impl SafetyAlert {
+    pub fn new(
+        trip_id: Uuid,
+        driver_id: Uuid,
+        kind: SafetyAlertKind,
+        coordinates: Coordinates,
+    ) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            trip_id,
+            driver_id,
+            kind,
+            coordinates,
+            timestamp: Utc::now(),
+        }
+    }
+}
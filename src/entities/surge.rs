@@ -0,0 +1,17 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::entities::Coordinates;
+
+/// The surge multiplier of a geohash cell, derived from the balance between searching trips
+/// originating in the cell and available drivers located in it.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SurgeCell {
+    pub cell: String,
+    pub min: Coordinates,
+    pub max: Coordinates,
+    pub demand: i32,
+    pub supply: i32,
+    pub multiplier: f64,
+    pub updated_at: DateTime<Utc>,
+}
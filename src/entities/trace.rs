@@ -0,0 +1,100 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::entities::Coordinates;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TracePoint {
+    pub coordinates: Coordinates,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// The locations reported by the assigned driver of a trip, in the order they were recorded.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TripTrace {
+    pub trip_id: Uuid,
+    pub points: Vec<TracePoint>,
+}
+
+impl TripTrace {
+    /// Returns the distance travelled along the trace, in meters.
+    pub fn distance(&self) -> f64 {
+        self.points
+            .windows(2)
+            .map(|pair| pair[0].coordinates.distance(&pair[1].coordinates))
+            .sum()
+    }
+
+    /// Returns the trace as a GeoJSON feature with a LineString geometry, keeping the timestamp
+    /// of each point in its properties.
+    pub fn to_geojson(&self) -> serde_json::Value {
+        let coordinates: Vec<[f64; 2]> = self
+            .points
+            .iter()
+            .map(|point| [point.coordinates.lng, point.coordinates.lat])
+            .collect();
+
+        let timestamps: Vec<&DateTime<Utc>> =
+            self.points.iter().map(|point| &point.timestamp).collect();
+
+        json!({
+            "type": "Feature",
+            "geometry": {
+                "type": "LineString",
+                "coordinates": coordinates,
+            },
+            "properties": {
+                "trip_id": self.trip_id,
+                "timestamps": timestamps,
+                "distance": self.distance(),
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_trace(points: &[(f64, f64)]) -> TripTrace {
+        TripTrace {
+            trip_id: Uuid::new_v4(),
+            points: points
+                .iter()
+                .map(|&(lat, lng)| TracePoint {
+                    coordinates: Coordinates { lat, lng },
+                    timestamp: Utc::now(),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn distance_test() {
+        assert_eq!(new_trace(&[]).distance(), 0.0);
+        assert_eq!(new_trace(&[(4.17, 73.51)]).distance(), 0.0);
+
+        // one degree of latitude is roughly 111 km
+        let distance = new_trace(&[(0.0, 0.0), (0.5, 0.0), (1.0, 0.0)]).distance();
+        assert!((distance - 111_195.0).abs() < 100.0);
+    }
+
+    #[test]
+    fn geojson_test() {
+        let trace = new_trace(&[(4.17, 73.51), (4.18, 73.52)]);
+        let geojson = trace.to_geojson();
+
+        assert_eq!(geojson["geometry"]["type"], "LineString");
+        assert_eq!(geojson["geometry"]["coordinates"][0][0], 73.51);
+        assert_eq!(geojson["geometry"]["coordinates"][0][1], 4.17);
+        assert_eq!(
+            geojson["properties"]["timestamps"]
+                .as_array()
+                .unwrap()
+                .len(),
+            2
+        );
+    }
+}
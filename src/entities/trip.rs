@@ -5,8 +5,8 @@ use oso::PolarClass;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-use crate::entities::Route;
-use crate::error::{invalid_invocation_error, Error};
+use crate::entities::{FareBreakdown, PoolStop, PoolStopKind, Route, VehicleClass};
+use crate::error::{invalid_input_error, invalid_invocation_error, Error};
 
 #[derive(Clone, Debug, Serialize, Deserialize, PolarClass)]
 pub struct Trip {
@@ -18,19 +18,51 @@ pub struct Trip {
     pub passenger_id: Uuid,
     pub route: Route,
     pub max_fare: f64,
+    pub surge_multiplier: f64,
     pub fare: Option<f64>,
+    pub fare_breakdown: Option<FareBreakdown>,
+    // the distance the assigned driver drove to the pickup, which is charged at their rate
+    pub pickup_distance: f64,
+    // the fare from the distance and duration actually travelled, when metering is enabled
+    pub metered_fare: Option<MeteredFare>,
+    // a change of destination requested by the passenger and awaiting the driver's acknowledgement
+    pub pending_route_change: Option<RouteChange>,
+    pub route_changes: Vec<RouteChange>,
+    // when the driver arrived at each stop of the route, in order
+    pub stop_arrivals: Vec<DateTime<Utc>>,
     #[polar(attribute)]
     pub driver_id: Option<Uuid>,
+    // keep searching for drivers in the background until one is found
+    pub wait_for_driver: bool,
+    // pooled trips may share the vehicle with other passengers at a discount
+    pub is_pooled: bool,
+    // only drivers with a vehicle of this class are dispatched to the trip
+    pub vehicle_class: VehicleClass,
+    // the rating the passenger gave the driver once the trip was completed, from one to five
+    pub rating: Option<i32>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(tag = "name", rename_all = "snake_case")]
 pub enum Status {
+    Scheduled {
+        pickup_at: DateTime<Utc>,
+        // a driver who committed to the booking, with the fare agreed at the time
+        driver_id: Option<Uuid>,
+        breakdown: Option<FareBreakdown>,
+    },
     Searching,
     PendingAssignment {
         deadline: DateTime<Utc>,
         driver_id: Uuid,
+        pickup_distance: f64,
         fare: f64,
+        breakdown: FareBreakdown,
+    },
+    // offered to several drivers at once, the first to accept is assigned
+    Broadcast {
+        deadline: DateTime<Utc>,
+        offers: Vec<BroadcastOffer>,
     },
     DriverEnRoute {
         deadline: DateTime<Utc>,
@@ -45,6 +77,37 @@ pub enum Status {
     Completed,
 }
 
+/// The offer of a broadcast trip to one of the drivers, at the fare of that driver.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BroadcastOffer {
+    pub driver_id: Uuid,
+    pub pickup_distance: f64,
+    pub fare: f64,
+    pub breakdown: FareBreakdown,
+}
+
+/// The fare of a trip metered from its trace between pickup and dropoff, with the distance in
+/// meters and the duration in seconds.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MeteredFare {
+    pub distance: f64,
+    pub duration: i64,
+    pub fare: f64,
+    pub breakdown: FareBreakdown,
+}
+
+/// A change of the route of a trip under way, with the fares before and after the change.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RouteChange {
+    pub previous_route: Route,
+    pub route: Route,
+    pub previous_fare: f64,
+    pub fare: f64,
+    pub breakdown: FareBreakdown,
+    pub requested_at: DateTime<Utc>,
+    pub acknowledged_at: Option<DateTime<Utc>>,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum PenaltyBearer {
@@ -55,12 +118,16 @@ pub enum PenaltyBearer {
 impl Status {
     pub fn name(&self) -> String {
         match self {
+            Self::Scheduled { .. } => "scheduled".into(),
             Self::Searching => "searching".into(),
             Self::PendingAssignment {
                 deadline: _,
                 driver_id: _,
+                pickup_distance: _,
                 fare: _,
-            } => "pending_assessment".into(),
+                breakdown: _,
+            } => "pending_assignment".into(),
+            Self::Broadcast { .. } => "broadcast".into(),
             Self::DriverEnRoute { deadline: _ } => "driver_en_route".into(),
             Self::DriverArrived {
                 is_late: _,
@@ -81,10 +148,19 @@ impl PolarClass for Status {
                 Status::PendingAssignment {
                     deadline: _,
                     driver_id,
+                    pickup_distance: _,
                     fare: _,
+                    breakdown: _,
                 } => Some(driver_id.clone()),
+                Status::Scheduled { driver_id, .. } => *driver_id,
                 _ => None,
             })
+            .add_attribute_getter("driver_ids", |recv: &Status| match recv {
+                Status::Broadcast { offers, .. } => {
+                    offers.iter().map(|offer| offer.driver_id).collect()
+                }
+                _ => vec![],
+            })
     }
 
     fn get_polar_class() -> oso::Class {
@@ -94,7 +170,7 @@ impl PolarClass for Status {
 }
 
 impl Trip {
-    pub fn new(passenger_id: Uuid, route: Route, max_fare: f64) -> Self {
+    pub fn new(passenger_id: Uuid, route: Route, max_fare: f64, surge_multiplier: f64) -> Self {
         let status = Status::Searching;
 
         Self {
@@ -103,8 +179,39 @@ impl Trip {
             passenger_id,
             route,
             max_fare,
+            surge_multiplier,
             fare: None,
+            fare_breakdown: None,
+            pickup_distance: 0.0,
+            metered_fare: None,
+            pending_route_change: None,
+            route_changes: vec![],
+            stop_arrivals: vec![],
             driver_id: None,
+            wait_for_driver: false,
+            is_pooled: false,
+            vehicle_class: VehicleClass::default(),
+            rating: None,
+        }
+    }
+
+    /// Creates a trip booked in advance, to be searched for a driver shortly before pickup.
+    pub fn new_scheduled(
+        passenger_id: Uuid,
+        route: Route,
+        max_fare: f64,
+        surge_multiplier: f64,
+        pickup_at: DateTime<Utc>,
+    ) -> Self {
+        Self {
+            status: Status::Scheduled {
+                pickup_at,
+                driver_id: None,
+                breakdown: None,
+            },
+            // keep searching from dispatch until pickup
+            wait_for_driver: true,
+            ..Self::new(passenger_id, route, max_fare, surge_multiplier)
         }
     }
 
@@ -115,14 +222,113 @@ impl Trip {
         }
     }
 
+    pub fn is_finished(&self) -> bool {
+        matches!(
+            self.status,
+            Status::Completed | Status::Cancelled { penalty_bearer: _ }
+        )
+    }
+
+    /// Returns when the passenger was picked up, if the trip is currently under way.
+    pub fn pickup_time(&self) -> Option<DateTime<Utc>> {
+        match self.status {
+            Status::DriverArrived {
+                is_late: _,
+                timestamp,
+            } => Some(timestamp),
+            _ => None,
+        }
+    }
+
+    /// Returns the pickup and dropoff of the trip as stops of a pool.
+    pub fn pool_stops(&self) -> (PoolStop, PoolStop) {
+        let stop = |kind, coordinates| PoolStop {
+            trip_id: self.id,
+            kind,
+            coordinates,
+        };
+
+        (
+            stop(PoolStopKind::Pickup, self.route.origin.coordinates.clone()),
+            stop(
+                PoolStopKind::Dropoff,
+                self.route.destination.coordinates.clone(),
+            ),
+        )
+    }
+
+    /// Pre-assigns a driver who commits to serve a scheduled trip at the given fare.
+    #[tracing::instrument]
+    pub fn commit_driver(
+        &mut self,
+        driver_id: Uuid,
+        breakdown: FareBreakdown,
+    ) -> Result<(), Error> {
+        match &mut self.status {
+            Status::Scheduled {
+                pickup_at: _,
+                driver_id: committed_driver_id @ None,
+                breakdown: committed_breakdown,
+            } if breakdown.total <= self.max_fare => {
+                *committed_driver_id = Some(driver_id);
+                *committed_breakdown = Some(breakdown);
+                Ok(())
+            }
+            _ => Err(invalid_invocation_error()),
+        }
+    }
+
+    /// Withdraws the committed driver of a scheduled trip, returning whether the driver withdrew
+    /// too close to pickup to avoid a penalty.
+    #[tracing::instrument]
+    pub fn withdraw_driver(&mut self, driver_id: Uuid) -> Result<bool, Error> {
+        match &mut self.status {
+            Status::Scheduled {
+                pickup_at,
+                driver_id: committed_driver_id,
+                breakdown,
+            } if *committed_driver_id == Some(driver_id) => {
+                *committed_driver_id = None;
+                *breakdown = None;
+                Ok(Utc::now() >= *pickup_at - Duration::hours(1))
+            }
+            _ => Err(invalid_invocation_error()),
+        }
+    }
+
+    /// Moves a scheduled trip into driver search, returning the committed driver and fare if any.
+    #[tracing::instrument]
+    pub fn start_search(&mut self) -> Result<Option<(Uuid, FareBreakdown)>, Error> {
+        match &self.status {
+            Status::Scheduled {
+                pickup_at: _,
+                driver_id,
+                breakdown,
+            } => {
+                let committed = driver_id.zip(breakdown.clone());
+
+                self.status = Status::Searching;
+                Ok(committed)
+            }
+            _ => Err(invalid_invocation_error()),
+        }
+    }
+
     #[tracing::instrument]
-    pub fn request_driver(&mut self, driver_id: Uuid, fare: f64) -> Result<(), Error> {
+    pub fn request_driver(
+        &mut self,
+        driver_id: Uuid,
+        pickup_distance: f64,
+        breakdown: FareBreakdown,
+    ) -> Result<(), Error> {
         match self.status {
             Status::Searching => {
                 self.status = Status::PendingAssignment {
                     deadline: Utc::now() + Duration::seconds(30),
                     driver_id,
-                    fare,
+                    pickup_distance,
+                    fare: breakdown.total,
+                    breakdown,
                 };
                 Ok(())
             }
@@ -130,34 +336,101 @@ impl Trip {
         }
     }
 
+    /// Offers the trip to several drivers at once.
     #[tracing::instrument]
-    pub fn release_driver(&mut self) -> Result<Uuid, Error> {
+    pub fn broadcast(&mut self, offers: Vec<BroadcastOffer>) -> Result<(), Error> {
         match self.status {
+            Status::Searching if !offers.is_empty() => {
+                self.status = Status::Broadcast {
+                    deadline: Utc::now() + Duration::seconds(30),
+                    offers,
+                };
+                Ok(())
+            }
+            _ => Err(invalid_invocation_error()),
+        }
+    }
+
+    /// Releases a requested driver, or withdraws the offer of a broadcast trip from one of the
+    /// drivers. The trip returns to searching once no driver remains.
+    #[tracing::instrument]
+    pub fn release_driver(&mut self, driver_id: Uuid) -> Result<(), Error> {
+        match &mut self.status {
             Status::PendingAssignment {
                 deadline: _,
-                driver_id,
+                driver_id: requested_driver_id,
+                pickup_distance: _,
                 fare: _,
-            } => {
+                breakdown: _,
+            } if *requested_driver_id == driver_id => {
                 self.status = Status::Searching;
-                Ok(driver_id)
+                Ok(())
+            }
+            Status::Broadcast { offers, .. }
+                if offers.iter().any(|offer| offer.driver_id == driver_id) =>
+            {
+                offers.retain(|offer| offer.driver_id != driver_id);
+
+                if offers.is_empty() {
+                    self.status = Status::Searching;
+                }
+                Ok(())
             }
             _ => Err(invalid_invocation_error()),
         }
     }
 
+    /// Assigns the first driver to accept a broadcast trip, returning the other drivers the
+    /// trip was offered to.
+    #[tracing::instrument]
+    pub fn accept_offer(&mut self, driver_id: Uuid) -> Result<Vec<Uuid>, Error> {
+        let (offer, others) = match &self.status {
+            Status::Broadcast { offers, .. } => {
+                let (accepted, others): (Vec<_>, Vec<_>) = offers
+                    .iter()
+                    .partition(|offer| offer.driver_id == driver_id);
+
+                match accepted.first() {
+                    Some(offer) => (
+                        (*offer).clone(),
+                        others.iter().map(|offer| offer.driver_id).collect(),
+                    ),
+                    None => return Err(invalid_invocation_error()),
+                }
+            }
+            _ => return Err(invalid_invocation_error()),
+        };
+
+        self.driver_id = Some(driver_id);
+        self.fare = Some(offer.fare);
+        self.fare_breakdown = Some(offer.breakdown);
+        self.pickup_distance = offer.pickup_distance;
+        self.status = Status::DriverEnRoute {
+            deadline: Utc::now() + Duration::minutes(15),
+        };
+
+        Ok(others)
+    }
+
     #[tracing::instrument]
     pub fn assign_driver(&mut self) -> Result<Uuid, Error> {
-        match self.status {
+        match &self.status {
             Status::PendingAssignment {
                 deadline: _,
                 driver_id,
+                pickup_distance,
                 fare,
+                breakdown,
             } => {
+                let driver_id = *driver_id;
+
+                self.driver_id = Some(driver_id);
+                self.fare = Some(*fare);
+                self.fare_breakdown = Some(breakdown.clone());
+                self.pickup_distance = *pickup_distance;
                 self.status = Status::DriverEnRoute {
                     deadline: Utc::now() + Duration::minutes(15),
                 };
-                self.driver_id = Some(driver_id.clone());
-                self.fare = Some(fare);
 
                 Ok(driver_id)
             }
@@ -179,13 +452,25 @@ impl Trip {
         }
     }
 
+    /// Records the arrival at the next stop of the route, returning the index of the stop.
+    #[tracing::instrument]
+    pub fn reach_stop(&mut self) -> Result<usize, Error> {
+        match self.status {
+            Status::DriverArrived { .. } if self.stop_arrivals.len() < self.route.stops.len() => {
+                self.stop_arrivals.push(Utc::now());
+                Ok(self.stop_arrivals.len() - 1)
+            }
+            _ => Err(invalid_invocation_error()),
+        }
+    }
+
     #[tracing::instrument]
     pub fn end_route(&mut self) -> Result<(), Error> {
         match self.status {
             Status::DriverArrived {
                 is_late: _,
                 timestamp: _,
-            } => {
+            } if self.stop_arrivals.len() == self.route.stops.len() => {
                 self.status = Status::Completed;
                 Ok(())
             }
@@ -193,12 +478,109 @@ impl Trip {
         }
     }
 
+    /// Records the rating the passenger gave the driver, which may only be given once the trip is
+    /// completed and cannot be changed.
     #[tracing::instrument]
-    pub fn cancel(&mut self, is_passenger: bool) -> Result<Option<Uuid>, Error> {
+    pub fn rate(&mut self, rating: i32) -> Result<(), Error> {
+        if !(1..=5).contains(&rating) {
+            return Err(invalid_input_error());
+        }
+
+        match self.status {
+            Status::Completed if self.rating.is_none() => {
+                self.rating = Some(rating);
+                Ok(())
+            }
+            _ => Err(invalid_invocation_error()),
+        }
+    }
+
+    /// Requests a change of route once a driver is assigned, replacing any change that is still
+    /// awaiting acknowledgement. The stops already arrived at must be kept, and the routes of
+    /// pooled trips are fixed as they are planned around the other passengers.
+    #[tracing::instrument]
+    pub fn request_route_change(
+        &mut self,
+        route: Route,
+        breakdown: FareBreakdown,
+    ) -> Result<(), Error> {
+        let previous_fare = match (&self.status, self.fare) {
+            (Status::DriverEnRoute { .. } | Status::DriverArrived { .. }, Some(fare))
+                if !self.is_pooled =>
+            {
+                fare
+            }
+            _ => return Err(invalid_invocation_error()),
+        };
+
+        // the stops already arrived at cannot be changed
+        let arrivals = self.stop_arrivals.len();
+        let is_changed = route.stops.len() < arrivals
+            || route
+                .stops
+                .iter()
+                .zip(self.route.stops.iter())
+                .take(arrivals)
+                .any(|(stop, previous)| stop.token != previous.token);
+
+        if is_changed {
+            return Err(invalid_input_error());
+        }
+
+        self.pending_route_change = Some(RouteChange {
+            previous_route: self.route.clone(),
+            route,
+            previous_fare,
+            fare: breakdown.total,
+            breakdown,
+            requested_at: Utc::now(),
+            acknowledged_at: None,
+        });
+
+        Ok(())
+    }
+
+    /// Applies the pending route change and its fare once the driver acknowledges it.
+    #[tracing::instrument]
+    pub fn acknowledge_route_change(&mut self) -> Result<(), Error> {
+        match &self.status {
+            Status::DriverEnRoute { .. } | Status::DriverArrived { .. } => {}
+            _ => return Err(invalid_invocation_error()),
+        }
+
+        let mut change = self
+            .pending_route_change
+            .take()
+            .ok_or_else(invalid_invocation_error)?;
+
+        change.acknowledged_at = Some(Utc::now());
+
+        self.route = change.route.clone();
+        self.fare = Some(change.fare);
+        self.fare_breakdown = Some(change.breakdown.clone());
+        self.route_changes.push(change);
+
+        Ok(())
+    }
+
+    /// Cancels the trip, returning the drivers freed by the cancellation.
+    #[tracing::instrument]
+    pub fn cancel(&mut self, is_passenger: bool) -> Result<Vec<Uuid>, Error> {
         let (penalty_bearer, freed_driver_id) = self.cancellation_result(is_passenger)?;
 
+        // every driver a broadcast trip is offered to is freed
+        let offered_driver_ids = match &self.status {
+            Status::Broadcast { offers, .. } => {
+                offers.iter().map(|offer| offer.driver_id).collect()
+            }
+            _ => vec![],
+        };
+
         self.status = Status::Cancelled { penalty_bearer };
-        Ok(freed_driver_id)
+        Ok(freed_driver_id
+            .into_iter()
+            .chain(offered_driver_ids)
+            .collect())
     }
 
     #[tracing::instrument]
@@ -207,11 +589,26 @@ impl Trip {
         is_passenger: bool,
     ) -> Result<(Option<PenaltyBearer>, Option<Uuid>), Error> {
         match &self.status {
-            Status::Searching => Ok((None, None)),
+            // passengers may cancel bookings free of charge until an hour before pickup once a
+            // driver has committed, and committed drivers withdraw rather than cancel
+            Status::Scheduled {
+                pickup_at,
+                driver_id,
+                breakdown: _,
+            } => match is_passenger {
+                true if driver_id.is_some() && Utc::now() >= *pickup_at - Duration::hours(1) => {
+                    Ok((Some(PenaltyBearer::Passenger), None))
+                }
+                true => Ok((None, None)),
+                false => Err(invalid_invocation_error()),
+            },
+            Status::Searching | Status::Broadcast { .. } => Ok((None, None)),
             Status::PendingAssignment {
                 deadline: _,
                 driver_id,
+                pickup_distance: _,
                 fare: _,
+                breakdown: _,
             } => Ok((None, Some(driver_id.clone()))),
             Status::DriverEnRoute { deadline } => match is_passenger {
                 true => {
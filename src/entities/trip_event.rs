@@ -0,0 +1,17 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use uuid::Uuid;
+
+/// A status transition of a trip, recorded in the append-only trip history.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TripEvent {
+    pub id: i64,
+    pub trip_id: Uuid,
+    pub actor_id: Uuid,
+    pub from_status: Option<String>,
+    pub to_status: String,
+    pub timestamp: DateTime<Utc>,
+    // the trip status after the transition
+    pub payload: Value,
+}
@@ -0,0 +1,12 @@
+use serde::{Deserialize, Serialize};
+
+use crate::entities::{DriverLocation, SafetyAlert, Trip};
+
+/// An update pushed to the subscribers of a trip.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TripUpdate {
+    Status { trip: Box<Trip> },
+    DriverLocation { location: DriverLocation },
+    SafetyAlert { alert: SafetyAlert },
+}
@@ -0,0 +1,58 @@
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// The vehicle a driver serves trips with.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Vehicle {
+    pub driver_id: Uuid,
+    pub make: String,
+    pub model: String,
+    pub plate: String,
+    // seats available to passengers
+    pub capacity: i32,
+    pub class: VehicleClass,
+    #[serde(default)]
+    pub accessibility: Vec<AccessibilityFeature>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VehicleClass {
+    #[default]
+    Economy,
+    Comfort,
+    Xl,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AccessibilityFeature {
+    Wheelchair,
+    ServiceAnimal,
+    ChildSeat,
+}
+
+impl Vehicle {
+    pub fn is_valid(&self) -> bool {
+        self.capacity >= 1 && !self.plate.trim().is_empty()
+    }
+}
+
+impl VehicleClass {
+    pub fn name(&self) -> String {
+        match self {
+            Self::Economy => "economy".into(),
+            Self::Comfort => "comfort".into(),
+            Self::Xl => "xl".into(),
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "economy" => Some(Self::Economy),
+            "comfort" => Some(Self::Comfort),
+            "xl" => Some(Self::Xl),
+            _ => None,
+        }
+    }
+}
@@ -115,6 +115,15 @@ pub fn invalid_input_error() -> Error {
     }
 }
 
+pub fn expired_error() -> Error {
+    tracing::info!("expired error");
+
+    Error {
+        code: 102,
+        message: "expired error".into(),
+    }
+}
+
 pub fn unauthorized_error() -> Error {
     tracing::info!("unauthorized error");
 
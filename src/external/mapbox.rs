@@ -1 +1,163 @@
+use serde::{Deserialize, Serialize};
+use std::env;
 
+use crate::{
+    entities::Coordinates,
+    error::{invalid_input_error, upstream_error, Error},
+};
+
+/// The driving distance in meters and duration in seconds between two points.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Leg {
+    pub distance: f64,
+    pub duration: f64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct MatrixResponse {
+    code: String,
+    distances: Option<Vec<Vec<Option<f64>>>>,
+    durations: Option<Vec<Vec<Option<f64>>>>,
+}
+
+/// The driving directions through a list of waypoints, with the geometry of the route as a
+/// GeoJSON LineString.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Directions {
+    pub distance: f64,
+    pub duration: f64,
+    pub geometry: serde_json::Value,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct DirectionsResponse {
+    code: String,
+    routes: Option<Vec<Directions>>,
+}
+
+// the matrix API accepts at most 25 coordinates per request
+pub const MAX_MATRIX_SOURCES: usize = 24;
+
+/// Returns the driving leg from each source to the destination, or `None` for sources that
+/// cannot be routed to the destination.
+#[tracing::instrument]
+pub async fn find_legs(
+    sources: &[Coordinates],
+    destination: &Coordinates,
+) -> Result<Vec<Option<Leg>>, Error> {
+    if sources.is_empty() || sources.len() > MAX_MATRIX_SOURCES {
+        return Err(invalid_input_error());
+    }
+
+    let coordinates = sources
+        .iter()
+        .chain(std::iter::once(destination))
+        .map(|coordinates| format!("{},{}", coordinates.lng, coordinates.lat))
+        .collect::<Vec<_>>()
+        .join(";");
+
+    let source_indices = (0..sources.len())
+        .map(|index| index.to_string())
+        .collect::<Vec<_>>()
+        .join(";");
+
+    let api_base = env::var("MAPBOX_API_BASE")?;
+    let url = format!(
+        "https://{}/directions-matrix/v1/mapbox/driving/{}",
+        api_base, coordinates
+    );
+    let access_token = env::var("MAPBOX_ACCESS_TOKEN")?;
+
+    let res = reqwest::Client::new()
+        .get(url)
+        .query(&[("access_token", access_token)])
+        .query(&[("sources", source_indices)])
+        .query(&[("destinations", sources.len().to_string())])
+        .query(&[("annotations", "distance,duration")])
+        .send()
+        .await?;
+
+    tracing::debug!("received response: {:?}", res);
+
+    let status_code = res.status().as_u16();
+
+    if (400..500).contains(&status_code) {
+        return Err(invalid_input_error());
+    } else if status_code != 200 {
+        return Err(upstream_error());
+    }
+
+    let data: MatrixResponse = res.json().await?;
+
+    if data.code != "Ok" {
+        return Err(upstream_error());
+    }
+
+    let distances = data.distances.ok_or_else(upstream_error)?;
+    let durations = data.durations.ok_or_else(upstream_error)?;
+
+    if distances.len() != sources.len() || durations.len() != sources.len() {
+        return Err(upstream_error());
+    }
+
+    Ok(distances
+        .iter()
+        .zip(durations.iter())
+        .map(|(distance, duration)| {
+            Some(Leg {
+                distance: (*distance.first()?)?,
+                duration: (*duration.first()?)?,
+            })
+        })
+        .collect())
+}
+
+/// Returns the driving directions through the waypoints in order.
+#[tracing::instrument]
+pub async fn find_directions(waypoints: &[Coordinates]) -> Result<Directions, Error> {
+    // the directions API accepts between 2 and 25 waypoints per request
+    if waypoints.len() < 2 || waypoints.len() > MAX_MATRIX_SOURCES + 1 {
+        return Err(invalid_input_error());
+    }
+
+    let coordinates = waypoints
+        .iter()
+        .map(|coordinates| format!("{},{}", coordinates.lng, coordinates.lat))
+        .collect::<Vec<_>>()
+        .join(";");
+
+    let api_base = env::var("MAPBOX_API_BASE")?;
+    let url = format!(
+        "https://{}/directions/v5/mapbox/driving/{}",
+        api_base, coordinates
+    );
+    let access_token = env::var("MAPBOX_ACCESS_TOKEN")?;
+
+    let res = reqwest::Client::new()
+        .get(url)
+        .query(&[("access_token", access_token)])
+        .query(&[("geometries", "geojson")])
+        .query(&[("overview", "full")])
+        .send()
+        .await?;
+
+    tracing::debug!("received response: {:?}", res);
+
+    let status_code = res.status().as_u16();
+
+    if (400..500).contains(&status_code) {
+        return Err(invalid_input_error());
+    } else if status_code != 200 {
+        return Err(upstream_error());
+    }
+
+    let data: DirectionsResponse = res.json().await?;
+
+    if data.code != "Ok" {
+        return Err(upstream_error());
+    }
+
+    data.routes
+        .and_then(|routes| routes.into_iter().next())
+        .ok_or_else(upstream_error)
+}
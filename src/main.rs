@@ -14,6 +14,7 @@ async fn main() {
         .unwrap();
 
     let engine = Engine::new(pool).await.unwrap();
+    engine.spawn_jobs();
 
     serve(engine).await;
 }
@@ -1,15 +1,25 @@
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
 use axum::extract::{Extension, Json, Path};
+use axum::response::Response;
+use futures::StreamExt;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+use crate::api::Subscription;
 use crate::auth::User;
-use crate::entities::{Coordinates, Driver};
-use crate::error::Error;
+use crate::entities::{
+    AccessibilityFeature, Coordinates, Driver, DriverStats, LocationSample, OfferUpdate, Trip,
+    Vehicle, VehicleClass,
+};
+use crate::error::{invalid_input_error, Error};
 use crate::server::DynAPI;
 
 #[derive(Serialize, Deserialize)]
 pub struct UpdateLocationParams {
     coordinates: Coordinates,
+    // whether the device reported the location as coming from a mock provider
+    #[serde(default)]
+    is_mock: bool,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -18,6 +28,40 @@ pub struct UpdateRateParams {
     rate: f64,
 }
 
+#[derive(Serialize, Deserialize)]
+pub struct RegisterVehicleParams {
+    make: String,
+    model: String,
+    plate: String,
+    capacity: i32,
+    class: VehicleClass,
+    #[serde(default)]
+    accessibility: Vec<AccessibilityFeature>,
+}
+
+// batches of locations sent by drivers over the locations socket
+#[derive(Serialize, Deserialize)]
+pub struct LocationBatch {
+    pub locations: Vec<LocationSample>,
+}
+
+// messages sent by drivers over the offers socket
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum OfferCommand {
+    Accept { trip_id: Uuid },
+    Reject { trip_id: Uuid },
+}
+
+// replies to offer commands, sent alongside offer updates
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum OfferReply {
+    Accepted { trip: Box<Trip> },
+    Rejected { trip: Box<Trip> },
+    Error { code: i32, message: String },
+}
+
 pub async fn create(
     Extension(api): Extension<DynAPI>,
     Extension(user): Extension<User>,
@@ -37,6 +81,47 @@ pub async fn find(
     Ok(driver.into())
 }
 
+pub async fn find_stats(
+    Extension(api): Extension<DynAPI>,
+    Extension(user): Extension<User>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<DriverStats>, Error> {
+    let stats = api.find_driver_stats(user, id).await?;
+
+    Ok(stats.into())
+}
+
+pub async fn find_vehicle(
+    Extension(api): Extension<DynAPI>,
+    Extension(user): Extension<User>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<Vehicle>, Error> {
+    let vehicle = api.find_vehicle(user, id).await?;
+
+    Ok(vehicle.into())
+}
+
+pub async fn register_vehicle(
+    Extension(api): Extension<DynAPI>,
+    Extension(user): Extension<User>,
+    Path(id): Path<Uuid>,
+    Json(params): Json<RegisterVehicleParams>,
+) -> Result<Json<Vehicle>, Error> {
+    let vehicle = Vehicle {
+        driver_id: id,
+        make: params.make,
+        model: params.model,
+        plate: params.plate,
+        capacity: params.capacity,
+        class: params.class,
+        accessibility: params.accessibility,
+    };
+
+    let vehicle = api.register_vehicle(user, vehicle).await?;
+
+    Ok(vehicle.into())
+}
+
 pub async fn start(
     Extension(api): Extension<DynAPI>,
     Extension(user): Extension<User>,
@@ -63,12 +148,45 @@ pub async fn update_location(
     Path(id): Path<Uuid>,
     Json(params): Json<UpdateLocationParams>,
 ) -> Result<Json<()>, Error> {
-    api.update_driver_location(user, id, params.coordinates)
+    api.update_driver_location(user, id, params.coordinates, params.is_mock)
         .await?;
 
     Ok(().into())
 }
 
+pub async fn ingest_locations(
+    Extension(api): Extension<DynAPI>,
+    Extension(user): Extension<User>,
+    Path(id): Path<Uuid>,
+    ws: WebSocketUpgrade,
+) -> Result<Response, Error> {
+    let sender = api.ingest_driver_locations(user, id).await?;
+
+    Ok(ws.on_upgrade(move |mut socket| async move {
+        while let Some(Ok(message)) = socket.recv().await {
+            let text = match message {
+                Message::Text(text) => text,
+                Message::Close(_) => break,
+                _ => continue,
+            };
+
+            let batch = match serde_json::from_str::<LocationBatch>(&text) {
+                Ok(batch) => batch,
+                Err(err) => {
+                    tracing::warn!("discarding invalid location batch: {:?}", err);
+                    continue;
+                }
+            };
+
+            if sender.send(batch.locations).await.is_err() {
+                break;
+            }
+        }
+
+        let _ = socket.close().await;
+    }))
+}
+
 pub async fn update_rate(
     Extension(api): Extension<DynAPI>,
     Extension(user): Extension<User>,
@@ -80,3 +198,79 @@ pub async fn update_rate(
 
     Ok(().into())
 }
+
+pub async fn offers(
+    Extension(api): Extension<DynAPI>,
+    Extension(user): Extension<User>,
+    Path(id): Path<Uuid>,
+    ws: WebSocketUpgrade,
+) -> Result<Response, Error> {
+    let offers = api.subscribe_offers(user.clone(), id).await?;
+
+    Ok(ws.on_upgrade(move |socket| handle_offers(socket, api, user, offers)))
+}
+
+async fn handle_offers(
+    mut socket: WebSocket,
+    api: DynAPI,
+    user: User,
+    mut offers: Subscription<OfferUpdate>,
+) {
+    loop {
+        let text = tokio::select! {
+            update = offers.next() => match update {
+                Some(update) => serde_json::to_string(&update),
+                None => break,
+            },
+            message = socket.recv() => match message {
+                Some(Ok(Message::Text(text))) => {
+                    let reply = handle_offer_command(&api, &user, &text).await;
+                    serde_json::to_string(&reply)
+                }
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => continue,
+            },
+        };
+
+        let text = match text {
+            Ok(text) => text,
+            Err(err) => {
+                tracing::error!("failed to serialize offer message: {:?}", err);
+                break;
+            }
+        };
+
+        if socket.send(Message::Text(text)).await.is_err() {
+            break;
+        }
+    }
+
+    let _ = socket.close().await;
+}
+
+async fn handle_offer_command(api: &DynAPI, user: &User, text: &str) -> OfferReply {
+    let result =
+        match serde_json::from_str::<OfferCommand>(text) {
+            Ok(OfferCommand::Accept { trip_id }) => api
+                .accept_trip(user.clone(), trip_id)
+                .await
+                .map(|trip| OfferReply::Accepted {
+                    trip: Box::new(trip),
+                }),
+            Ok(OfferCommand::Reject { trip_id }) => api
+                .reject_trip(user.clone(), trip_id)
+                .await
+                .map(|trip| OfferReply::Rejected {
+                    trip: Box::new(trip),
+                }),
+            Err(_) => Err(invalid_input_error()),
+        };
+
+    result.unwrap_or_else(|err| OfferReply::Error {
+        code: err.code,
+        message: match err.code {
+            0..=99 => "internal server error".into(),
+            _ => err.message,
+        },
+    })
+}
@@ -3,4 +3,6 @@ pub mod google_places;
 pub mod locations;
 pub mod quotes;
 pub mod routes;
+pub mod safety;
+pub mod surge;
 pub mod trips;
@@ -1,15 +1,25 @@
 use axum::extract::{Extension, Json, Path};
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
 use crate::auth::User;
-use crate::entities::Quote;
+use crate::entities::{Quote, VehicleClass};
 use crate::error::Error;
 use crate::server::DynAPI;
 
 #[derive(Serialize, Deserialize)]
 pub struct CreateParams {
     route_token: Uuid,
+    // books the trip in advance for the given pickup time
+    #[serde(default)]
+    pickup_at: Option<DateTime<Utc>>,
+    // shares the vehicle with other passengers at a discount
+    #[serde(default)]
+    pooled: bool,
+    // only drivers with a vehicle of this class serve the trip
+    #[serde(default)]
+    vehicle_class: VehicleClass,
 }
 
 pub async fn create(
@@ -17,7 +27,15 @@ pub async fn create(
     Extension(user): Extension<User>,
     Json(params): Json<CreateParams>,
 ) -> Result<Json<Option<Quote>>, Error> {
-    let quote = api.create_quote(user, params.route_token).await?;
+    let quote = api
+        .create_quote(
+            user,
+            params.route_token,
+            params.pickup_at,
+            params.pooled,
+            params.vehicle_class,
+        )
+        .await?;
 
     Ok(quote.into())
 }
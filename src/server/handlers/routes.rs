@@ -9,6 +9,9 @@ use crate::{entities::Route, error::Error};
 #[derive(Serialize, Deserialize)]
 pub struct CreateParams {
     origin_id: Uuid,
+    // intermediate stops, in the order they are visited
+    #[serde(default)]
+    stop_ids: Vec<Uuid>,
     destination_id: Uuid,
 }
 
@@ -18,7 +21,12 @@ pub async fn create(
     Json(params): Json<CreateParams>,
 ) -> Result<Json<Route>, Error> {
     let route = api
-        .create_route(user, params.origin_id, params.destination_id)
+        .create_route(
+            user,
+            params.origin_id,
+            params.stop_ids,
+            params.destination_id,
+        )
         .await?;
 
     Ok(route.into())
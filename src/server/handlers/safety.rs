@@ -0,0 +1,26 @@
+use axum::extract::{Extension, Json, Path};
+use uuid::Uuid;
+
+use crate::auth::User;
+use crate::entities::SafetyAlert;
+use crate::error::Error;
+use crate::server::DynAPI;
+
+pub async fn find_alerts(
+    Extension(api): Extension<DynAPI>,
+    Extension(user): Extension<User>,
+) -> Result<Json<Vec<SafetyAlert>>, Error> {
+    let alerts = api.find_safety_alerts(user).await?;
+
+    Ok(alerts.into())
+}
+
+pub async fn find_trip_alerts(
+    Extension(api): Extension<DynAPI>,
+    Extension(user): Extension<User>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<Vec<SafetyAlert>>, Error> {
+    let alerts = api.find_trip_alerts(user, id).await?;
+
+    Ok(alerts.into())
+}
@@ -0,0 +1,15 @@
+use axum::extract::{Extension, Json};
+
+use crate::auth::User;
+use crate::entities::SurgeCell;
+use crate::error::Error;
+use crate::server::DynAPI;
+
+pub async fn find(
+    Extension(api): Extension<DynAPI>,
+    Extension(user): Extension<User>,
+) -> Result<Json<Vec<SurgeCell>>, Error> {
+    let cells = api.find_surge(user).await?;
+
+    Ok(cells.into())
+}
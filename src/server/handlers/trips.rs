@@ -1,15 +1,31 @@
-use axum::extract::{Extension, Json, Path};
+use axum::extract::{ws::WebSocketUpgrade, Extension, Json, Path};
+use axum::response::{IntoResponse, Response};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
 use crate::auth::User;
-use crate::entities::Trip;
+use crate::entities::{ScheduledTripListing, Trip, TripEvent};
 use crate::error::Error;
-use crate::server::DynAPI;
+use crate::server::{stream, DynAPI};
 
 #[derive(Serialize, Deserialize)]
 pub struct CreateParams {
     quote_token: Uuid,
+    #[serde(default)]
+    wait_for_driver: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ChangeDestinationParams {
+    // replaces the stops of the trip, which are otherwise kept
+    #[serde(default)]
+    stop_tokens: Option<Vec<Uuid>>,
+    destination_token: Uuid,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RateParams {
+    rating: i32,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -22,7 +38,9 @@ pub async fn create(
     Extension(user): Extension<User>,
     Json(params): Json<CreateParams>,
 ) -> Result<Json<Trip>, Error> {
-    let trip = api.create_trip(user, params.quote_token).await?;
+    let trip = api
+        .create_trip(user, params.quote_token, params.wait_for_driver)
+        .await?;
 
     Ok(trip.into())
 }
@@ -37,6 +55,47 @@ pub async fn find(
     Ok(trip.into())
 }
 
+pub async fn find_history(
+    Extension(api): Extension<DynAPI>,
+    Extension(user): Extension<User>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<Vec<TripEvent>>, Error> {
+    let events = api.find_trip_history(user, id).await?;
+
+    Ok(events.into())
+}
+
+pub async fn find_trace(
+    Extension(api): Extension<DynAPI>,
+    Extension(user): Extension<User>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<serde_json::Value>, Error> {
+    let trace = api.find_trip_trace(user, id).await?;
+
+    Ok(trace.to_geojson().into())
+}
+
+pub async fn stream_updates(
+    Extension(api): Extension<DynAPI>,
+    Extension(user): Extension<User>,
+    Path(id): Path<Uuid>,
+) -> Result<Response, Error> {
+    let updates = api.subscribe_trip(user, id).await?;
+
+    Ok(stream::sse(updates).into_response())
+}
+
+pub async fn socket_updates(
+    Extension(api): Extension<DynAPI>,
+    Extension(user): Extension<User>,
+    Path(id): Path<Uuid>,
+    ws: WebSocketUpgrade,
+) -> Result<Response, Error> {
+    let updates = api.subscribe_trip(user, id).await?;
+
+    Ok(ws.on_upgrade(|socket| stream::forward(socket, updates)))
+}
+
 pub async fn request_driver(
     Extension(api): Extension<DynAPI>,
     Extension(user): Extension<User>,
@@ -87,3 +146,105 @@ pub async fn cancel(
 
     Ok(trip.into())
 }
+
+pub async fn change_destination(
+    Extension(api): Extension<DynAPI>,
+    Extension(user): Extension<User>,
+    Path(id): Path<Uuid>,
+    Json(params): Json<ChangeDestinationParams>,
+) -> Result<Json<Trip>, Error> {
+    let trip = api
+        .change_destination(user, id, params.stop_tokens, params.destination_token)
+        .await?;
+
+    Ok(trip.into())
+}
+
+pub async fn acknowledge_route_change(
+    Extension(api): Extension<DynAPI>,
+    Extension(user): Extension<User>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<Trip>, Error> {
+    let trip = api.acknowledge_route_change(user, id).await?;
+
+    Ok(trip.into())
+}
+
+pub async fn rate(
+    Extension(api): Extension<DynAPI>,
+    Extension(user): Extension<User>,
+    Path(id): Path<Uuid>,
+    Json(params): Json<RateParams>,
+) -> Result<Json<Trip>, Error> {
+    let trip = api.rate_trip(user, id, params.rating).await?;
+
+    Ok(trip.into())
+}
+
+pub async fn report_origin_arrival(
+    Extension(api): Extension<DynAPI>,
+    Extension(user): Extension<User>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<Trip>, Error> {
+    let trip = api.report_origin_arrival(user, id).await?;
+
+    Ok(trip.into())
+}
+
+pub async fn report_stop_arrival(
+    Extension(api): Extension<DynAPI>,
+    Extension(user): Extension<User>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<Trip>, Error> {
+    let trip = api.report_stop_arrival(user, id).await?;
+
+    Ok(trip.into())
+}
+
+pub async fn report_destination_arrival(
+    Extension(api): Extension<DynAPI>,
+    Extension(user): Extension<User>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<Trip>, Error> {
+    let trip = api.report_destination_arrival(user, id).await?;
+
+    Ok(trip.into())
+}
+
+pub async fn find_scheduled(
+    Extension(api): Extension<DynAPI>,
+    Extension(user): Extension<User>,
+) -> Result<Json<Vec<Trip>>, Error> {
+    let trips = api.find_scheduled_trips(user).await?;
+
+    Ok(trips.into())
+}
+
+pub async fn find_scheduled_listings(
+    Extension(api): Extension<DynAPI>,
+    Extension(user): Extension<User>,
+) -> Result<Json<Vec<ScheduledTripListing>>, Error> {
+    let listings = api.find_scheduled_listings(user).await?;
+
+    Ok(listings.into())
+}
+
+pub async fn commit(
+    Extension(api): Extension<DynAPI>,
+    Extension(user): Extension<User>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<Trip>, Error> {
+    let trip = api.commit_to_trip(user, id).await?;
+
+    Ok(trip.into())
+}
+
+pub async fn withdraw(
+    Extension(api): Extension<DynAPI>,
+    Extension(user): Extension<User>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<Trip>, Error> {
+    let trip = api.withdraw_from_trip(user, id).await?;
+
+    Ok(trip.into())
+}
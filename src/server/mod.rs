@@ -1,4 +1,5 @@
 mod handlers;
+mod stream;
 
 use std::net::SocketAddr;
 use std::sync::Arc;
@@ -9,13 +10,15 @@ use axum::{
     Router,
 };
 
-use crate::server::handlers::{drivers, google_places, locations, quotes, routes, trips};
+use crate::server::handlers::{
+    drivers, google_places, locations, quotes, routes, safety, surge, trips,
+};
 use crate::{api::API, auth::User};
 
 type DynAPI = Arc<dyn API + Send + Sync>;
 
-pub async fn serve<T: API + Sync + Send + 'static>(api: T) {
-    let api = Arc::new(api) as DynAPI;
+pub async fn serve<T: API + Sync + Send + 'static>(api: Arc<T>) {
+    let api = api as DynAPI;
 
     let app = Router::new()
         .route("/locations", post(locations::create))
@@ -26,17 +29,53 @@ pub async fn serve<T: API + Sync + Send + 'static>(api: T) {
         .route("/quotes/:token", get(quotes::find))
         .route("/trips", post(trips::create))
         .route("/trips/:id", get(trips::find))
+        .route("/trips/:id/history", get(trips::find_history))
+        .route("/trips/:id/trace", get(trips::find_trace))
+        .route("/trips/:id/alerts", get(safety::find_trip_alerts))
+        .route("/trips/:id/updates", get(trips::stream_updates))
+        .route("/trips/:id/socket", get(trips::socket_updates))
         .route("/trips/:id/driver/request", patch(trips::request_driver))
         .route("/trips/:id/driver/release", patch(trips::release_driver))
         .route("/trips/:id/driver/accept", patch(trips::accept_trip))
         .route("/trips/:id/driver/reject", patch(trips::reject_trip))
         .route("/trips/:id/cancel", patch(trips::cancel))
+        .route("/trips/:id/rate", patch(trips::rate))
+        .route("/trips/:id/commit", patch(trips::commit))
+        .route("/trips/:id/withdraw", patch(trips::withdraw))
+        .route(
+            "/trips/:id/origin/arrive",
+            patch(trips::report_origin_arrival),
+        )
+        .route("/trips/:id/stops/arrive", patch(trips::report_stop_arrival))
+        .route(
+            "/trips/:id/destination/arrive",
+            patch(trips::report_destination_arrival),
+        )
+        .route("/trips/:id/destination", patch(trips::change_destination))
+        .route(
+            "/trips/:id/destination/acknowledge",
+            patch(trips::acknowledge_route_change),
+        )
+        .route("/scheduled_trips", get(trips::find_scheduled))
+        .route(
+            "/scheduled_trips/listings",
+            get(trips::find_scheduled_listings),
+        )
         .route("/drivers", post(drivers::create))
         .route("/drivers/:id", get(drivers::find))
+        .route("/drivers/:id/stats", get(drivers::find_stats))
+        .route(
+            "/drivers/:id/vehicle",
+            get(drivers::find_vehicle).put(drivers::register_vehicle),
+        )
         .route("/drivers/:id/start", patch(drivers::start))
         .route("/drivers/:id/stop", patch(drivers::stop))
         .route("/drivers/:id/location", patch(drivers::update_location))
+        .route("/drivers/:id/locations", get(drivers::ingest_locations))
+        .route("/drivers/:id/offers", get(drivers::offers))
         .route("/drivers/:id/rate", patch(drivers::update_rate))
+        .route("/surge", get(surge::find))
+        .route("/safety/alerts", get(safety::find_alerts))
         .route(
             "/google_places/suggestions",
             get(google_places::find_suggestions),
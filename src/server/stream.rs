@@ -0,0 +1,48 @@
+use axum::extract::ws::{Message, WebSocket};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use futures::{Stream, StreamExt};
+use serde::Serialize;
+
+use crate::api::Subscription;
+
+/// Streams each item of the subscription as a JSON encoded server-sent event.
+pub fn sse<T: Serialize + 'static>(
+    subscription: Subscription<T>,
+) -> Sse<impl Stream<Item = Result<Event, serde_json::Error>>> {
+    let events = subscription.map(|item| Event::default().json_data(item));
+
+    Sse::new(events).keep_alive(KeepAlive::default())
+}
+
+/// Sends each item of the subscription as a JSON encoded text message until either the
+/// subscription ends or the socket is closed by the client.
+pub async fn forward<T: Serialize>(mut socket: WebSocket, mut subscription: Subscription<T>) {
+    loop {
+        tokio::select! {
+            item = subscription.next() => {
+                let item = match item {
+                    Some(item) => item,
+                    None => break,
+                };
+
+                let text = match serde_json::to_string(&item) {
+                    Ok(text) => text,
+                    Err(err) => {
+                        tracing::error!("failed to serialize subscription item: {:?}", err);
+                        break;
+                    }
+                };
+
+                if socket.send(Message::Text(text)).await.is_err() {
+                    break;
+                }
+            }
+            message = socket.recv() => match message {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+
+    let _ = socket.close().await;
+}